@@ -1,14 +1,120 @@
-use reqwest::{Client, RequestBuilder};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::warn;
+use reqwest::{Client, StatusCode};
 use rust_decimal::Decimal;
 use serde::Deserialize;
+use tokio::time::sleep;
 
+use crate::config::BinanceConfig;
 use crate::error::{Result, ServiceError};
+use crate::exchange::{Exchange, ExchangeId, Precision, SymbolInfo};
 use crate::order_book::{OrderBook, OrderBookEntity};
+use crate::rate_limit::WeightLimiter;
+
+pub const SPOT_BASE_URL: &str = "https://api.binance.com";
+pub const SPOT_FALLBACK_URLS: [&str; 5] = [
+    "https://api1.binance.com",
+    "https://api2.binance.com",
+    "https://api3.binance.com",
+    "https://api4.binance.com",
+    "https://api-gcp.binance.com",
+];
+pub const FUTURES_BASE_URL: &str = "https://fapi.binance.com";
+
+const USED_WEIGHT_HEADER: &str = "X-MBX-USED-WEIGHT-1M";
+const DEFAULT_RETRY_AFTER_SECS: u64 = 60;
+/// Retries never wait longer than this, whatever `max_retries` is
+const MAX_BACKOFF_MS: u64 = 30_000;
+
+struct Endpoint {
+    path: &'static str,
+    weight: u32, // see the API docs of each endpoint
+    timeout: Duration,
+}
+
+struct BinanceEndpoints {
+    exchange: ExchangeId,
+    hosts: Vec<String>,
+    exchange_info: Endpoint,
+    order_book: Endpoint,
+    last_prices: Endpoint,
+    order_book_depth: &'static str,
+    weight_limit: u32,
+}
 
-const EXCHANGE_INFO_URL: &str = "https://api.binance.com/api/v3/exchangeInfo";
-const ORDER_BOOK_URL: &str = "https://api.binance.com/api/v3/depth";
-const LAST_PRICES_URL: &str = "https://api.binance.com/api/v3/ticker/price";
-const ORDER_BOOK_DEPTH: &str = "5000"; // maximum available depth
+impl BinanceEndpoints {
+    fn spot(config: &BinanceConfig) -> Self {
+        BinanceEndpoints {
+            exchange: ExchangeId::Binance,
+            hosts: config.spot_hosts(),
+            exchange_info: Endpoint {
+                path: "/api/v3/exchangeInfo",
+                weight: 20,
+                timeout: Duration::from_millis(config.timeouts.exchange_info_ms),
+            },
+            order_book: Endpoint {
+                path: "/api/v3/depth",
+                weight: 250,
+                timeout: Duration::from_millis(config.timeouts.order_book_ms),
+            },
+            last_prices: Endpoint {
+                path: "/api/v3/ticker/price",
+                weight: 2,
+                timeout: Duration::from_millis(config.timeouts.last_price_ms),
+            },
+            order_book_depth: "5000", // maximum available depth
+            weight_limit: 6000,
+        }
+    }
+
+    fn usd_m_futures(config: &BinanceConfig) -> Self {
+        BinanceEndpoints {
+            exchange: ExchangeId::BinanceFutures,
+            hosts: vec![config.futures_url.clone()],
+            exchange_info: Endpoint {
+                path: "/fapi/v1/exchangeInfo",
+                weight: 1,
+                timeout: Duration::from_millis(config.timeouts.exchange_info_ms),
+            },
+            order_book: Endpoint {
+                path: "/fapi/v1/depth",
+                weight: 20,
+                timeout: Duration::from_millis(config.timeouts.order_book_ms),
+            },
+            last_prices: Endpoint {
+                path: "/fapi/v1/ticker/price",
+                weight: 1,
+                timeout: Duration::from_millis(config.timeouts.last_price_ms),
+            },
+            order_book_depth: "1000", // maximum available depth
+            weight_limit: 2400,
+        }
+    }
+}
+
+/// Outcome of a failed attempt, decides whether the request is retried.
+enum AttemptError {
+    /// Host is unreachable, the next attempt goes to another host
+    Connection(ServiceError),
+    /// Timeout or server side error
+    Transient(ServiceError),
+    Fatal(ServiceError),
+}
+
+impl From<reqwest::Error> for AttemptError {
+    fn from(value: reqwest::Error) -> Self {
+        if value.is_connect() {
+            AttemptError::Connection(ServiceError::Unavailable(value.to_string()))
+        } else if value.is_timeout() {
+            AttemptError::Transient(ServiceError::Unavailable(value.to_string()))
+        } else {
+            AttemptError::Fatal(ServiceError::from(value))
+        }
+    }
+}
 
 #[allow(unused)]
 #[derive(Debug, Deserialize)]
@@ -19,25 +125,30 @@ pub struct BinanceError {
     pub message: String,
 }
 
-impl From<BinanceError> for crate::error::ServiceError {
-    fn from(value: BinanceError) -> Self {
-        Self::Internal(value.message)
+impl BinanceError {
+    // error codes, see https://developers.binance.com/docs/binance-spot-api-docs/errors
+    const DISCONNECTED: i32 = -1001;
+    const TOO_MANY_REQUESTS: i32 = -1003;
+    const TIMEOUT: i32 = -1007;
+    const SERVER_BUSY: i32 = -1008;
+    const INVALID_SYMBOL: i32 = -1121;
+
+    /// Maps the error onto a `ServiceError`, `symbol` is the one the request was made for, if any
+    fn into_service_error(self, symbol: Option<&str>) -> ServiceError {
+        match (self.status, symbol) {
+            (Self::INVALID_SYMBOL, Some(symbol)) => ServiceError::SymbolNotFound(symbol.to_string(), None),
+            (Self::TOO_MANY_REQUESTS, _) => ServiceError::RateLimited(DEFAULT_RETRY_AFTER_SECS),
+            (Self::DISCONNECTED | Self::TIMEOUT | Self::SERVER_BUSY, _) => ServiceError::Unavailable(self.message),
+            // 11xx codes are request parameter errors
+            (code, _) if (-1199..=-1100).contains(&code) => ServiceError::BadRequest(self.message),
+            (code, _) => ServiceError::internal(format!("Binance error {}: {}", code, self.message)),
+        }
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum BinanceResponseWrapper<T> {
-    Ok(T),
-    Err(BinanceError),
-}
-
-impl<T> BinanceResponseWrapper<T> {
-    fn into_result(self) -> Result<T> {
-        match self {
-            BinanceResponseWrapper::Ok(value) => Ok(value),
-            BinanceResponseWrapper::Err(e) => Err(ServiceError::from(e)),
-        }
+impl From<BinanceError> for crate::error::ServiceError {
+    fn from(value: BinanceError) -> Self {
+        value.into_service_error(None)
     }
 }
 
@@ -45,29 +156,29 @@ impl<T> BinanceResponseWrapper<T> {
 #[serde(untagged)]
 enum BinanceResponse<T> {
     Ok(T),
-    None,
+    Err(BinanceError),
 }
 
 impl<T> BinanceResponse<T> {
-    fn into_result(self) -> Result<T> {
+    fn into_result(self) -> std::result::Result<T, BinanceError> {
         match self {
             BinanceResponse::Ok(value) => Ok(value),
-            BinanceResponse::None => Err(ServiceError::internal(
-                "Binance returned no data".to_string(),
-            )),
+            BinanceResponse::Err(e) => Err(e),
         }
     }
 }
 
 #[derive(Debug, Deserialize)]
-pub struct BinancePriceResponse {
+struct BinancePriceResponse {
     #[allow(unused)]
-    pub symbol: String,
-    pub price: Decimal,
+    symbol: String,
+    price: Decimal,
 }
 
 #[derive(Debug, Deserialize)]
 struct BinanceOrderBookResponse {
+    #[serde(rename = "lastUpdateId")]
+    last_update_id: u64,
     bids: Vec<(Decimal, Decimal)>, // (price, qty)
     asks: Vec<(Decimal, Decimal)>, // (price, qty)
 }
@@ -77,7 +188,7 @@ impl From<BinanceOrderBookResponse> for OrderBook {
         let to_entity = |entity: Vec<(Decimal, Decimal)>| -> Vec<OrderBookEntity> {
             entity
                 .into_iter()
-                .map(|(price, qty)| OrderBookEntity { price, qty })
+                .map(|(price, qty)| OrderBookEntity::new(price, qty))
                 .collect()
         };
 
@@ -88,73 +199,275 @@ impl From<BinanceOrderBookResponse> for OrderBook {
     }
 }
 
+/// Order book snapshot along with the id of the last update included in it,
+/// needed to continue the book from the depth stream.
+#[derive(Debug)]
+pub struct DepthSnapshot {
+    pub last_update_id: u64,
+    pub book: OrderBook,
+}
+
+impl From<BinanceOrderBookResponse> for DepthSnapshot {
+    fn from(value: BinanceOrderBookResponse) -> Self {
+        DepthSnapshot {
+            last_update_id: value.last_update_id,
+            book: value.into(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
-pub struct BinanceExchangeSymbol {
-    pub symbol: String,
-    pub status: String,
+#[serde(tag = "filterType")]
+enum BinanceSymbolFilter {
+    #[serde(rename = "PRICE_FILTER", rename_all = "camelCase")]
+    Price { tick_size: Decimal },
+    #[serde(rename = "LOT_SIZE", rename_all = "camelCase")]
+    LotSize { step_size: Decimal },
+    #[serde(other)]
+    Other,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BinanceExchangeSymbol {
+    symbol: String,
+    status: String,
+    // futures only, spot symbols have no contract type
+    contract_type: Option<String>,
+    #[serde(default)]
+    filters: Vec<BinanceSymbolFilter>,
+}
+
+impl BinanceExchangeSymbol {
+    fn precision(&self) -> Option<Precision> {
+        let tick_size = self.filters.iter().find_map(|filter| match filter {
+            BinanceSymbolFilter::Price { tick_size } => Some(*tick_size),
+            _ => None,
+        })?;
 
+        let step_size = self.filters.iter().find_map(|filter| match filter {
+            BinanceSymbolFilter::LotSize { step_size } => Some(*step_size),
+            _ => None,
+        })?;
+
+        Some(Precision { tick_size, step_size })
+    }
+}
+
+impl From<BinanceExchangeSymbol> for SymbolInfo {
+    fn from(value: BinanceExchangeSymbol) -> Self {
+        let is_perpetual = value
+            .contract_type
+            .as_ref()
+            .is_none_or(|contract_type| contract_type == "PERPETUAL");
+
+        SymbolInfo {
+            is_trading: value.status == "TRADING" && is_perpetual,
+            precision: value.precision(),
+            symbol: value.symbol,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
 struct BinanceExchangeInfoResponse {
     symbols: Vec<BinanceExchangeSymbol>,
 }
 
 pub struct Binance {
     client: Client,
+    endpoints: BinanceEndpoints,
+    weight: WeightLimiter,
+    host: AtomicUsize,
+    max_retries: u32,
+    retry_base_delay: Duration,
 }
 
 impl Binance {
-    pub fn new() -> Self {
+    pub fn new(config: &BinanceConfig) -> Self {
+        Self::with_endpoints(BinanceEndpoints::spot(config), config)
+    }
+
+    pub fn futures(config: &BinanceConfig) -> Self {
+        Self::with_endpoints(BinanceEndpoints::usd_m_futures(config), config)
+    }
+
+    fn with_endpoints(endpoints: BinanceEndpoints, config: &BinanceConfig) -> Self {
         Self {
             client: Client::new(),
+            weight: WeightLimiter::new(endpoints.weight_limit),
+            endpoints,
+            host: AtomicUsize::new(0),
+            max_retries: config.max_retries,
+            retry_base_delay: Duration::from_millis(config.retry_base_delay_ms),
         }
     }
 
-    fn request(&self, url: &str) -> RequestBuilder {
-        self.client.get(url)
+    fn rotate_host(&self, failed: usize) {
+        let next = (failed + 1) % self.endpoints.hosts.len();
+
+        // another request may have rotated already
+        let _ = self
+            .host
+            .compare_exchange(failed, next, Ordering::Relaxed, Ordering::Relaxed);
     }
 
-    fn request_with_params(&self, url: &str, params: &[(&str, &str)]) -> RequestBuilder {
-        self.request(url).query(params)
+    /// Exponential backoff with up to one base delay of random jitter, capped at `MAX_BACKOFF_MS`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let base = (self.retry_base_delay.as_millis() as u64).min(MAX_BACKOFF_MS);
+        let jitter = rand::random_range(0..=base);
+        let delay = 2u64
+            .checked_pow(attempt)
+            .map_or(u64::MAX, |factor| base.saturating_mul(factor));
+
+        Duration::from_millis(delay.saturating_add(jitter).min(MAX_BACKOFF_MS))
     }
 
-    async fn send_request<T: for<'a> Deserialize<'a>>(&self, request: RequestBuilder) -> Result<T> {
-        request
+    async fn send_attempt<T: for<'a> Deserialize<'a>>(
+        &self,
+        host: usize,
+        endpoint: &Endpoint,
+        params: &[(&str, &str)],
+    ) -> std::result::Result<T, AttemptError> {
+        self.weight.acquire(endpoint.weight).map_err(AttemptError::Fatal)?;
+
+        let url = format!("{}{}", self.endpoints.hosts[host], endpoint.path);
+        let resp = self
+            .client
+            .get(url)
+            .query(params)
+            .timeout(endpoint.timeout)
             .send()
-            .await?
-            .json::<T>()
-            .await
-            .map_err(ServiceError::from)
+            .await?;
+
+        let used_weight = resp
+            .headers()
+            .get(USED_WEIGHT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u32>().ok());
+
+        if let Some(used_weight) = used_weight {
+            self.weight.update_used(used_weight);
+        }
+
+        // 429 is a warning, 418 means the IP is already banned
+        if matches!(resp.status(), StatusCode::TOO_MANY_REQUESTS | StatusCode::IM_A_TEAPOT) {
+            let retry_after = resp
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(DEFAULT_RETRY_AFTER_SECS);
+
+            self.weight.block_for(retry_after);
+            return Err(AttemptError::Fatal(ServiceError::RateLimited(retry_after)));
+        }
+
+        // WAF limit violation, retrying right away won't help
+        if resp.status() == StatusCode::FORBIDDEN {
+            let msg = "Binance rejected the request with 403".to_string();
+            return Err(AttemptError::Fatal(ServiceError::Unavailable(msg)));
+        }
+
+        if resp.status().is_server_error() {
+            let msg = format!("Binance returned {}", resp.status());
+            return Err(AttemptError::Transient(ServiceError::Unavailable(msg)));
+        }
+
+        Ok(resp.json::<T>().await?)
+    }
+
+    async fn send_request<T: for<'a> Deserialize<'a>>(
+        &self,
+        endpoint: &Endpoint,
+        params: &[(&str, &str)],
+    ) -> Result<T> {
+        let mut attempt = 0;
+
+        loop {
+            let host = self.host.load(Ordering::Relaxed);
+
+            let error = match self.send_attempt::<T>(host, endpoint, params).await {
+                Ok(value) => return Ok(value),
+                Err(AttemptError::Fatal(e)) => return Err(e),
+                Err(AttemptError::Connection(e)) => {
+                    self.rotate_host(host);
+                    e
+                }
+                Err(AttemptError::Transient(e)) => e,
+            };
+
+            if attempt >= self.max_retries {
+                return Err(error);
+            }
+
+            warn!("Binance request {} failed, retrying: {}", endpoint.path, error);
+            sleep(self.backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    /// Maps an error body onto a `ServiceError`. A -1003 means the weight is already
+    /// over the limit, so further requests are held back locally.
+    fn handle_error(&self, error: BinanceError, symbol: Option<&str>) -> ServiceError {
+        let error = error.into_service_error(symbol);
+
+        if let ServiceError::RateLimited(secs) = error {
+            self.weight.block_for(secs);
+        }
+
+        error
     }
 
-    pub async fn get_last_price(&self, symbol: &str) -> Result<BinancePriceResponse> {
-        let req = self.request_with_params(LAST_PRICES_URL, &[("symbol", symbol)]);
-        self.send_request::<BinanceResponse<BinancePriceResponse>>(req)
+    pub async fn get_depth_snapshot(&self, symbol: &str) -> Result<DepthSnapshot> {
+        let params = &[("symbol", symbol), ("limit", self.endpoints.order_book_depth)];
+
+        let resp = self
+            .send_request::<BinanceResponse<BinanceOrderBookResponse>>(&self.endpoints.order_book, params)
             .await?
             .into_result()
+            .map_err(|e| self.handle_error(e, Some(symbol)))?;
+
+        Ok(resp.into())
     }
+}
 
-    pub async fn get_order_book(&self, symbol: &str) -> Result<OrderBook> {
-        let params = &[("symbol", symbol), ("limit", ORDER_BOOK_DEPTH)];
-        let req = self.request_with_params(ORDER_BOOK_URL, params);
+#[async_trait]
+impl Exchange for Binance {
+    fn id(&self) -> ExchangeId {
+        self.endpoints.exchange
+    }
 
+    async fn get_last_price(&self, symbol: &str) -> Result<Decimal> {
+        let params = &[("symbol", symbol)];
         let resp = self
-            .send_request::<BinanceResponse<BinanceOrderBookResponse>>(req)
+            .send_request::<BinanceResponse<BinancePriceResponse>>(&self.endpoints.last_prices, params)
             .await?
-            .into_result()?;
+            .into_result()
+            .map_err(|e| self.handle_error(e, Some(symbol)))?;
 
-        Ok(resp.into())
+        Ok(resp.price)
     }
 
-    pub async fn get_exchange_info(&self) -> Result<Vec<BinanceExchangeSymbol>> {
-        let req = self.request(EXCHANGE_INFO_URL);
+    async fn get_order_book(&self, symbol: &str) -> Result<OrderBook> {
+        let snapshot = self.get_depth_snapshot(symbol).await?;
 
+        Ok(snapshot.book)
+    }
+
+    async fn get_exchange_info(&self) -> Result<Vec<SymbolInfo>> {
         let resp = self
-            .send_request::<BinanceResponseWrapper<BinanceExchangeInfoResponse>>(req)
+            .send_request::<BinanceResponse<BinanceExchangeInfoResponse>>(
+                &self.endpoints.exchange_info,
+                &[],
+            )
             .await?
-            .into_result()?
-            .symbols;
+            .into_result()
+            .map_err(|e| self.handle_error(e, None))?
+            .symbols
+            .into_iter()
+            .map(SymbolInfo::from)
+            .collect();
 
         Ok(resp)
     }
@@ -162,29 +475,279 @@ impl Binance {
 
 #[cfg(test)]
 mod test {
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
     use super::*;
 
-    #[ignore]
+    fn config(uri: &str) -> BinanceConfig {
+        BinanceConfig {
+            spot_url: uri.to_string(),
+            futures_url: uri.to_string(),
+            retry_base_delay_ms: 1,
+            ..Default::default()
+        }
+    }
+
+    async fn mock_server(endpoint: &str, fixture: &str) -> MockServer {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path(endpoint))
+            .respond_with(ResponseTemplate::new(200).set_body_string(fixture))
+            .mount(&server)
+            .await;
+
+        server
+    }
+
     #[tokio::test]
     async fn test_get_last_price() {
-        let binance = Binance::new();
+        let fixture = include_str!("../fixtures/binance/ticker_price.json");
+        let server = mock_server("/api/v3/ticker/price", fixture).await;
+
+        let binance = Binance::new(&config(&server.uri()));
+        let result = binance.get_last_price("SOLUSDT").await.unwrap();
+        assert_eq!(result, Decimal::new(14652, 2));
+    }
+
+    #[tokio::test]
+    async fn test_get_order_book() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v3/depth"))
+            .and(query_param("symbol", "SOLUSDT"))
+            .and(query_param("limit", "5000"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(include_str!("../fixtures/binance/depth.json")))
+            .mount(&server)
+            .await;
+
+        let binance = Binance::new(&config(&server.uri()));
+        let result = binance.get_depth_snapshot("SOLUSDT").await.unwrap();
+
+        assert_eq!(result.last_update_id, 71592683521);
+        assert_eq!(result.book.asks.len(), 3);
+        assert_eq!(result.book.asks[0], OrderBookEntity::new(Decimal::new(14652, 2), Decimal::new(44987, 3)));
+        assert_eq!(result.book.bids[0], OrderBookEntity::new(Decimal::new(14651, 2), Decimal::new(182416, 3)));
+    }
+
+    #[tokio::test]
+    async fn test_get_order_book_invalid_symbol() {
+        let fixture = include_str!("../fixtures/binance/invalid_symbol.json");
+        let server = mock_server("/api/v3/depth", fixture).await;
+
+        let binance = Binance::new(&config(&server.uri()));
+        let result = binance.get_order_book("UNKNOWNUSDT").await;
+        assert!(matches!(result, Err(ServiceError::SymbolNotFound(symbol, None)) if symbol == "UNKNOWNUSDT"));
+    }
+
+    #[tokio::test]
+    async fn test_server_error_exhausts_retries() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v3/ticker/price"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(4)
+            .mount(&server)
+            .await;
+
+        let binance = Binance::new(&config(&server.uri()));
         let result = binance.get_last_price("SOLUSDT").await;
-        println!("Result: {:?}", result);
+        assert!(matches!(result, Err(ServiceError::Unavailable(_))));
+    }
+
+    #[tokio::test]
+    async fn test_get_exchange_info() {
+        let fixture = include_str!("../fixtures/binance/exchange_info.json");
+        let server = mock_server("/api/v3/exchangeInfo", fixture).await;
+
+        let binance = Binance::new(&config(&server.uri()));
+        let result = binance.get_exchange_info().await.unwrap();
+
+        let expected = Precision {
+            tick_size: Decimal::new(1, 2),
+            step_size: Decimal::new(1, 3),
+        };
+        assert_eq!(result[0].precision, Some(expected));
+
+        let trading = result
+            .into_iter()
+            .filter(|item| item.is_trading)
+            .map(|item| item.symbol)
+            .collect::<Vec<_>>();
+        assert_eq!(trading, vec!["SOLUSDT".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_get_futures_exchange_info() {
+        let fixture = include_str!("../fixtures/binance/futures_exchange_info.json");
+        let server = mock_server("/fapi/v1/exchangeInfo", fixture).await;
+
+        let binance = Binance::futures(&config(&server.uri()));
+        let result = binance.get_exchange_info().await.unwrap();
+
+        let trading = result
+            .into_iter()
+            .filter(|item| item.is_trading)
+            .map(|item| item.symbol)
+            .collect::<Vec<_>>();
+        assert_eq!(trading, vec!["SOLUSDT".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_response() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v3/depth"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "30"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let binance = Binance::new(&config(&server.uri()));
+
+        let result = binance.get_order_book("SOLUSDT").await;
+        assert!(matches!(result, Err(ServiceError::RateLimited(30))));
+
+        // rejected locally without reaching the server
+        let result = binance.get_order_book("SOLUSDT").await;
+        assert!(matches!(result, Err(ServiceError::RateLimited(_))));
+    }
+
+    #[tokio::test]
+    async fn test_too_many_requests_error_blocks_requests() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v3/ticker/price"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"{"code":-1003,"msg":"Too many requests."}"#),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let binance = Binance::new(&config(&server.uri()));
+
+        let result = binance.get_last_price("SOLUSDT").await;
+        assert!(matches!(result, Err(ServiceError::RateLimited(DEFAULT_RETRY_AFTER_SECS))));
+
+        // rejected locally without reaching the server
+        let result = binance.get_last_price("SOLUSDT").await;
+        assert!(matches!(result, Err(ServiceError::RateLimited(_))));
+    }
+
+    #[tokio::test]
+    async fn test_used_weight_header() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v3/ticker/price"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header(USED_WEIGHT_HEADER, "5900")
+                    .set_body_string(include_str!("../fixtures/binance/ticker_price.json")),
+            )
+            .mount(&server)
+            .await;
+
+        let binance = Binance::new(&config(&server.uri()));
+        assert!(binance.get_last_price("SOLUSDT").await.is_ok());
+
+        let result = binance.get_order_book("SOLUSDT").await;
+        assert!(matches!(result, Err(ServiceError::RateLimited(_))));
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_server_error() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v3/ticker/price"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v3/ticker/price"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(include_str!("../fixtures/binance/ticker_price.json")))
+            .mount(&server)
+            .await;
+
+        let binance = Binance::new(&config(&server.uri()));
+        let result = binance.get_last_price("SOLUSDT").await.unwrap();
+        assert_eq!(result, Decimal::new(14652, 2));
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let binance = Binance::new(&BinanceConfig {
+            retry_base_delay_ms: 1000,
+            ..Default::default()
+        });
+
+        let first = binance.backoff(0).as_millis();
+        assert!((1000..=2000).contains(&first));
+        assert_eq!(binance.backoff(10).as_millis(), MAX_BACKOFF_MS as u128);
+        assert_eq!(binance.backoff(100).as_millis(), MAX_BACKOFF_MS as u128);
+
+        let binance = Binance::new(&BinanceConfig {
+            retry_base_delay_ms: u64::MAX,
+            ..Default::default()
+        });
+        assert_eq!(binance.backoff(3).as_millis(), MAX_BACKOFF_MS as u128);
+    }
+
+    #[test]
+    fn test_spot_hosts() {
+        let default = BinanceConfig::default();
+        assert_eq!(default.spot_hosts().len(), 1 + SPOT_FALLBACK_URLS.len());
+
+        // a custom host never falls back to mainnet unless asked to
+        let mock = config("http://127.0.0.1:9");
+        assert_eq!(mock.spot_hosts(), vec!["http://127.0.0.1:9".to_string()]);
+
+        let disabled = BinanceConfig {
+            spot_fallback_urls: Some(vec![]),
+            ..Default::default()
+        };
+        assert_eq!(disabled.spot_hosts(), vec![SPOT_BASE_URL.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_failover_to_next_host() {
+        let fixture = include_str!("../fixtures/binance/ticker_price.json");
+        let server = mock_server("/api/v3/ticker/price", fixture).await;
+
+        // nothing listens on the discard port
+        let config = BinanceConfig {
+            spot_fallback_urls: Some(vec![server.uri()]),
+            ..config("http://127.0.0.1:9")
+        };
+
+        let binance = Binance::new(&config);
+        let result = binance.get_last_price("SOLUSDT").await.unwrap();
+        assert_eq!(result, Decimal::new(14652, 2));
+        assert_eq!(binance.host.load(Ordering::Relaxed), 1);
     }
 
     #[ignore]
     #[tokio::test]
-    async fn test_get_order_book() {
-        let binance = Binance::new();
+    async fn test_get_live_order_book() {
+        let binance = Binance::new(&BinanceConfig::default());
         let result = binance.get_order_book("SOLUSDT").await;
         println!("Result: {:?}", result);
     }
 
     #[ignore]
     #[tokio::test]
-    async fn test_get_exchange_info() {
-        let binance = Binance::new();
-        let result = binance.get_exchange_info().await;
+    async fn test_get_live_futures_order_book() {
+        let binance = Binance::futures(&BinanceConfig::default());
+        let result = binance.get_order_book("SOLUSDT").await;
         println!("Result: {:?}", result);
     }
 }
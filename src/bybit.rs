@@ -0,0 +1,263 @@
+use std::marker::PhantomData;
+
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+use crate::error::{Result, ServiceError};
+use crate::exchange::{Exchange, ExchangeId, Precision, SymbolInfo};
+use crate::order_book::{OrderBook, OrderBookEntity};
+
+const INSTRUMENTS_INFO_URL: &str = "https://api.bybit.com/v5/market/instruments-info";
+const ORDER_BOOK_URL: &str = "https://api.bybit.com/v5/market/orderbook";
+const TICKERS_URL: &str = "https://api.bybit.com/v5/market/tickers";
+const CATEGORY: &str = "spot";
+const ORDER_BOOK_DEPTH: &str = "200"; // maximum available depth for spot
+
+/// `result` is an empty object on errors, so it is only parsed once `ret_code` is checked
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitResponse<T> {
+    ret_code: i32,
+    ret_msg: String,
+    result: Option<serde_json::Value>,
+    #[serde(skip)]
+    data: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> BybitResponse<T> {
+    fn into_result(self) -> Result<T> {
+        match (self.ret_code, self.result) {
+            (0, Some(value)) => serde_json::from_value(value).map_err(ServiceError::from),
+            (0, None) => Err(ServiceError::internal("Bybit returned no data".to_string())),
+            (code, _) => Err(ServiceError::internal(format!(
+                "Bybit error {}: {}",
+                code, self.ret_msg
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BybitList<T> {
+    list: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitTicker {
+    #[allow(unused)]
+    symbol: String,
+    last_price: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+struct BybitOrderBookResponse {
+    #[serde(rename = "b")]
+    bids: Vec<(Decimal, Decimal)>, // (price, qty)
+    #[serde(rename = "a")]
+    asks: Vec<(Decimal, Decimal)>, // (price, qty)
+}
+
+impl From<BybitOrderBookResponse> for OrderBook {
+    fn from(value: BybitOrderBookResponse) -> Self {
+        let to_entity = |entity: Vec<(Decimal, Decimal)>| -> Vec<OrderBookEntity> {
+            entity
+                .into_iter()
+                .map(|(price, qty)| OrderBookEntity::new(price, qty))
+                .collect()
+        };
+
+        let asks = to_entity(value.asks);
+        let bids = to_entity(value.bids);
+
+        OrderBook { asks, bids }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitPriceFilter {
+    tick_size: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitLotSizeFilter {
+    base_precision: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitInstrument {
+    symbol: String,
+    status: String,
+    price_filter: Option<BybitPriceFilter>,
+    lot_size_filter: Option<BybitLotSizeFilter>,
+}
+
+impl From<BybitInstrument> for SymbolInfo {
+    fn from(value: BybitInstrument) -> Self {
+        let precision = value
+            .price_filter
+            .zip(value.lot_size_filter)
+            .map(|(price, lot)| Precision {
+                tick_size: price.tick_size,
+                step_size: lot.base_precision,
+            });
+
+        SymbolInfo {
+            is_trading: value.status == "Trading",
+            symbol: value.symbol,
+            precision,
+        }
+    }
+}
+
+pub struct Bybit {
+    client: Client,
+}
+
+impl Bybit {
+    pub fn new() -> Self {
+        Self {
+            client: Client::new(),
+        }
+    }
+
+    fn request_with_params(&self, url: &str, params: &[(&str, &str)]) -> RequestBuilder {
+        self.client.get(url).query(&[("category", CATEGORY)]).query(params)
+    }
+
+    async fn send_request<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        request
+            .send()
+            .await?
+            .json::<BybitResponse<T>>()
+            .await?
+            .into_result()
+    }
+}
+
+#[async_trait]
+impl Exchange for Bybit {
+    fn id(&self) -> ExchangeId {
+        ExchangeId::Bybit
+    }
+
+    async fn get_last_price(&self, symbol: &str) -> Result<Decimal> {
+        let req = self.request_with_params(TICKERS_URL, &[("symbol", symbol)]);
+
+        self.send_request::<BybitList<BybitTicker>>(req)
+            .await?
+            .list
+            .into_iter()
+            .next()
+            .map(|ticker| ticker.last_price)
+            .ok_or_else(|| ServiceError::internal(format!("Bybit returned no ticker for {}", symbol)))
+    }
+
+    async fn get_order_book(&self, symbol: &str) -> Result<OrderBook> {
+        let params = &[("symbol", symbol), ("limit", ORDER_BOOK_DEPTH)];
+        let req = self.request_with_params(ORDER_BOOK_URL, params);
+
+        let resp = self.send_request::<BybitOrderBookResponse>(req).await?;
+
+        Ok(resp.into())
+    }
+
+    async fn get_exchange_info(&self) -> Result<Vec<SymbolInfo>> {
+        let req = self.request_with_params(INSTRUMENTS_INFO_URL, &[]);
+
+        let resp = self
+            .send_request::<BybitList<BybitInstrument>>(req)
+            .await?
+            .list
+            .into_iter()
+            .map(SymbolInfo::from)
+            .collect();
+
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_order_book() {
+        let data = r#"{"retCode":0,"retMsg":"OK","result":{"s":"SOLUSDT","a":[["101.5","2"]],"b":[["99.5","4"]],"ts":1,"u":1}}"#;
+
+        let resp = serde_json::from_str::<BybitResponse<BybitOrderBookResponse>>(data)
+            .unwrap()
+            .into_result()
+            .unwrap();
+
+        let expected = OrderBook {
+            asks: vec![OrderBookEntity::new(Decimal::new(1015, 1), Decimal::TWO)],
+            bids: vec![OrderBookEntity::new(Decimal::new(995, 1), Decimal::from(4))],
+        };
+
+        assert_eq!(OrderBook::from(resp), expected);
+    }
+
+    #[test]
+    fn test_parse_error_response() {
+        let data = r#"{"retCode":10001,"retMsg":"Not supported symbols","result":{}}"#;
+
+        let result = serde_json::from_str::<BybitResponse<BybitOrderBookResponse>>(data)
+            .unwrap()
+            .into_result();
+
+        assert!(matches!(result, Err(ServiceError::Internal(msg)) if msg == "Bybit error 10001: Not supported symbols"));
+    }
+
+    #[test]
+    fn test_parse_instrument() {
+        let data = r#"{"symbol":"SOLUSDT","baseCoin":"SOL","quoteCoin":"USDT","status":"Trading",
+            "lotSizeFilter":{"basePrecision":"0.001","quotePrecision":"0.0000001","minOrderQty":"0.001"},
+            "priceFilter":{"tickSize":"0.01"}}"#;
+
+        let info = SymbolInfo::from(serde_json::from_str::<BybitInstrument>(data).unwrap());
+
+        assert_eq!(info.symbol, "SOLUSDT");
+        assert!(info.is_trading);
+        let expected = Precision {
+            tick_size: Decimal::new(1, 2),
+            step_size: Decimal::new(1, 3),
+        };
+        assert_eq!(info.precision, Some(expected));
+
+        let data = r#"{"symbol":"SOLUSDT","status":"PreLaunch"}"#;
+        let info = SymbolInfo::from(serde_json::from_str::<BybitInstrument>(data).unwrap());
+        assert!(!info.is_trading);
+        assert_eq!(info.precision, None);
+    }
+
+    #[ignore]
+    #[tokio::test]
+    async fn test_get_last_price() {
+        let bybit = Bybit::new();
+        let result = bybit.get_last_price("SOLUSDT").await;
+        println!("Result: {:?}", result);
+    }
+
+    #[ignore]
+    #[tokio::test]
+    async fn test_get_order_book() {
+        let bybit = Bybit::new();
+        let result = bybit.get_order_book("SOLUSDT").await;
+        println!("Result: {:?}", result);
+    }
+
+    #[ignore]
+    #[tokio::test]
+    async fn test_get_exchange_info() {
+        let bybit = Bybit::new();
+        let result = bybit.get_exchange_info().await;
+        println!("Result: {:?}", result);
+    }
+}
@@ -1,23 +1,123 @@
 use std::fs;
 use std::collections::HashSet;
 
+use rust_decimal::Decimal;
 use serde::Deserialize;
 use teloxide::types::ChatId;
 
+use crate::binance::{FUTURES_BASE_URL, SPOT_BASE_URL, SPOT_FALLBACK_URLS};
 use crate::error::{ServiceError, Result};
+use crate::local_book::STREAM_BASE_URL;
 
 const CONFIG_PATH: &str = "./configs/config.json";
 
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct BinanceTimeouts {
+    pub exchange_info_ms: u64,
+    pub order_book_ms: u64,
+    pub last_price_ms: u64,
+}
+
+impl Default for BinanceTimeouts {
+    fn default() -> Self {
+        BinanceTimeouts {
+            exchange_info_ms: 10_000,
+            order_book_ms: 5_000,
+            last_price_ms: 2_000,
+        }
+    }
+}
+
+/// Binance hosts, can point to testnet, a regional mirror or a local mock server
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct BinanceConfig {
+    pub spot_url: String,
+    /// Hosts tried in turn when `spot_url` is unreachable. Unset means the Binance
+    /// mirrors for the default host and none for any other one.
+    pub spot_fallback_urls: Option<Vec<String>>,
+    pub futures_url: String,
+    pub stream_url: String,
+    pub max_retries: u32,
+    pub retry_base_delay_ms: u64,
+    pub timeouts: BinanceTimeouts,
+}
+
+impl BinanceConfig {
+    /// `spot_url` followed by its fallbacks, a testnet or mock `spot_url` never fails over to mainnet
+    pub fn spot_hosts(&self) -> Vec<String> {
+        let fallbacks = match &self.spot_fallback_urls {
+            Some(urls) => urls.clone(),
+            None if self.spot_url == SPOT_BASE_URL => SPOT_FALLBACK_URLS.map(String::from).to_vec(),
+            None => Vec::new(),
+        };
+
+        std::iter::once(self.spot_url.clone()).chain(fallbacks).collect()
+    }
+}
+
+impl Default for BinanceConfig {
+    fn default() -> Self {
+        BinanceConfig {
+            spot_url: SPOT_BASE_URL.to_string(),
+            spot_fallback_urls: None,
+            futures_url: FUTURES_BASE_URL.to_string(),
+            stream_url: STREAM_BASE_URL.to_string(),
+            max_retries: 3,
+            retry_base_delay_ms: 200,
+            timeouts: BinanceTimeouts::default(),
+        }
+    }
+}
+
+/// Symbols whose walls are snapshotted in the background, see `/walls`
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct WallTrackingConfig {
+    pub symbols: Vec<String>,
+    pub interval_secs: u64,
+    /// Depth in percent the tracked walls are taken from
+    pub depth: Decimal,
+}
+
+impl Default for WallTrackingConfig {
+    fn default() -> Self {
+        WallTrackingConfig {
+            symbols: Vec::new(),
+            interval_secs: 60,
+            depth: Decimal::from(5),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ServiceConfig {
     pub redis_url: String,
     pub telegram_token: String,
-    pub allowed_users: HashSet<ChatId>
+    pub allowed_users: HashSet<ChatId>,
+    /// Binance spot symbols kept in memory from the depth stream instead of REST snapshots
+    #[serde(default)]
+    pub hot_symbols: Vec<String>,
+    #[serde(default)]
+    pub binance: BinanceConfig,
+    #[serde(default)]
+    pub wall_tracking: WallTrackingConfig,
 }
 
 impl ServiceConfig {
     pub fn read_from_file() -> Result<ServiceConfig> {
         let file = fs::read(CONFIG_PATH)?;
-        serde_json::from_slice::<ServiceConfig>(&file).map_err(ServiceError::from)
+        let config = serde_json::from_slice::<ServiceConfig>(&file)?;
+
+        let depth = config.wall_tracking.depth;
+        if depth <= Decimal::ZERO || depth >= Decimal::ONE_HUNDRED {
+            return Err(ServiceError::internal(format!(
+                "wall_tracking.depth should be a percentage between 0 and 100, got {}",
+                depth
+            )));
+        }
+
+        Ok(config)
     }
 }
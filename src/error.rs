@@ -1,12 +1,20 @@
 use std::{error::Error, fmt::Display};
 
+use crate::exchange::Market;
+
 pub type Result<T> = std::result::Result<T, ServiceError>;
 
 #[derive(Debug)]
 pub enum ServiceError {
-    SymbolNotFound(String),
-    UnsupportedSymbol(String),
+    /// Symbol and, if any, the other market it is listed on
+    SymbolNotFound(String, Option<Market>),
     Unauthorized,
+    /// Seconds until the exchange accepts requests again
+    RateLimited(u64),
+    /// Exchange is down, overloaded or unreachable
+    Unavailable(String),
+    /// Exchange refused the request parameters
+    BadRequest(String),
     Internal(String),
 }
 
@@ -14,6 +22,11 @@ impl ServiceError {
     pub fn internal(msg: String) -> Self {
         Self::Internal(msg)
     }
+
+    /// Errors worth logging, the rest are caused by user input or exchange limits
+    pub fn is_unexpected(&self) -> bool {
+        matches!(self, Self::Unavailable(_) | Self::BadRequest(_) | Self::Internal(_))
+    }
 }
 
 impl<E: Error> From<E> for ServiceError {
@@ -26,9 +39,17 @@ impl Display for ServiceError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let val = match &self {
             ServiceError::Internal(msg) => msg,
-            ServiceError::SymbolNotFound(symbol) => &format!("{} not found", symbol),
-            ServiceError::UnsupportedSymbol(symbol) => &format!("{} not supported", symbol),
-            ServiceError::Unauthorized => &format!("Action not allowed"),
+            ServiceError::SymbolNotFound(symbol, None) => &format!("{} not found", symbol),
+            ServiceError::SymbolNotFound(symbol, Some(listed)) => &format!(
+                "{} is listed on {} but not on {}",
+                symbol,
+                listed.to_string().to_lowercase(),
+                listed.other().to_string().to_lowercase()
+            ),
+            ServiceError::Unauthorized => "Action not allowed",
+            ServiceError::RateLimited(secs) => &format!("Exchange is busy, try again in {} seconds", secs),
+            ServiceError::Unavailable(msg) => &format!("Exchange is unavailable: {}", msg),
+            ServiceError::BadRequest(msg) => &format!("Exchange rejected the request: {}", msg),
         };
 
         write!(f, "{}", val)
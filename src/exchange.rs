@@ -0,0 +1,163 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, ServiceError};
+use crate::order_book::OrderBook;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ExchangeId {
+    Binance,
+    Bybit,
+    Okx,
+    BinanceFutures,
+}
+
+impl ExchangeId {
+    pub fn market(&self) -> Market {
+        match self {
+            ExchangeId::BinanceFutures => Market::Futures,
+            _ => Market::Spot,
+        }
+    }
+}
+
+impl Display for ExchangeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let val = match self {
+            ExchangeId::Binance => "Binance",
+            ExchangeId::Bybit => "Bybit",
+            ExchangeId::Okx => "OKX",
+            ExchangeId::BinanceFutures => "Binance Futures",
+        };
+
+        write!(f, "{}", val)
+    }
+}
+
+impl FromStr for ExchangeId {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "Binance" => Ok(ExchangeId::Binance),
+            "Bybit" => Ok(ExchangeId::Bybit),
+            "OKX" => Ok(ExchangeId::Okx),
+            "Binance Futures" => Ok(ExchangeId::BinanceFutures),
+            _ => Err(ServiceError::internal(format!("Unknown exchange {}", s))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Market {
+    Spot,
+    Futures,
+}
+
+impl Market {
+    pub fn other(&self) -> Market {
+        match self {
+            Market::Spot => Market::Futures,
+            Market::Futures => Market::Spot,
+        }
+    }
+}
+
+impl Display for Market {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let val = match self {
+            Market::Spot => "Spot",
+            Market::Futures => "Futures",
+        };
+
+        write!(f, "{}", val)
+    }
+}
+
+impl FromStr for Market {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "Spot" => Ok(Market::Spot),
+            "Futures" => Ok(Market::Futures),
+            _ => Err(ServiceError::internal(format!("Unknown market {}", s))),
+        }
+    }
+}
+
+/// Where an order book comes from: a single exchange or all exchanges listing the symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderBookSource {
+    Exchange(ExchangeId),
+    Aggregated,
+}
+
+impl Display for OrderBookSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderBookSource::Exchange(exchange) => write!(f, "{}", exchange),
+            OrderBookSource::Aggregated => write!(f, "All exchanges"),
+        }
+    }
+}
+
+impl FromStr for OrderBookSource {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "All exchanges" => Ok(OrderBookSource::Aggregated),
+            exchange => exchange.parse().map(OrderBookSource::Exchange),
+        }
+    }
+}
+
+/// Minimal price and quantity increments of a symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Precision {
+    pub tick_size: Decimal,
+    pub step_size: Decimal,
+}
+
+impl Precision {
+    pub fn round_price(&self, price: Decimal) -> Decimal {
+        price.trunc_with_scale(self.tick_size.normalize().scale()).normalize()
+    }
+
+    pub fn round_qty(&self, qty: Decimal) -> Decimal {
+        qty.trunc_with_scale(self.step_size.normalize().scale()).normalize()
+    }
+
+    /// The finer of two precisions, used when books of several exchanges are merged
+    pub fn finest(self, other: Precision) -> Precision {
+        Precision {
+            tick_size: self.tick_size.min(other.tick_size),
+            step_size: self.step_size.min(other.step_size),
+        }
+    }
+}
+
+/// Symbol as listed by a venue, normalized to the `BASEQUOTE` form (e.g. `SOLUSDT`).
+#[derive(Debug)]
+pub struct SymbolInfo {
+    pub symbol: String,
+    pub is_trading: bool,
+    /// `None` if the venue didn't report the increments
+    pub precision: Option<Precision>,
+}
+
+#[async_trait]
+pub trait Exchange: Send + Sync {
+    fn id(&self) -> ExchangeId;
+
+    async fn get_last_price(&self, symbol: &str) -> Result<Decimal>;
+
+    async fn get_order_book(&self, symbol: &str) -> Result<OrderBook>;
+
+    async fn get_exchange_info(&self) -> Result<Vec<SymbolInfo>>;
+}
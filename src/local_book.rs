@@ -0,0 +1,617 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{Stream, StreamExt};
+use futures::future::join_all;
+use log::{error, info};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use tokio::sync::RwLock;
+use tokio::time::{sleep, timeout};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use crate::binance::{Binance, DepthSnapshot};
+use crate::error::{Result, ServiceError};
+use crate::order_book::{OrderBook, OrderBookEntity, OrderType};
+use crate::time::unix_now;
+
+pub const STREAM_BASE_URL: &str = "wss://stream.binance.com:9443";
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Binance pings every 20 seconds, a silent stream longer than this is considered dead
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// Trades older than this are dropped, wall tracking has to snapshot more often to see fills
+const TRADES_TTL_SECS: u64 = 900;
+/// A level is an iceberg once this many times its largest visible size traded there
+const ICEBERG_RATIO: Decimal = Decimal::from_parts(3, 0, 0, false, 0);
+/// Trades at a level needed to tell a replenished level from a single large fill
+const ICEBERG_MIN_TRADES: usize = 3;
+const ICEBERGS_LIMIT: usize = 10;
+
+#[derive(Debug, Deserialize)]
+struct StreamMessage {
+    data: StreamEvent,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "e")]
+enum StreamEvent {
+    #[serde(rename = "depthUpdate")]
+    DepthUpdate(DepthUpdate),
+    #[serde(rename = "aggTrade")]
+    AggTrade(AggTrade),
+}
+
+#[derive(Debug, Deserialize)]
+struct DepthUpdate {
+    #[serde(rename = "U")]
+    first_update_id: u64,
+    #[serde(rename = "u")]
+    final_update_id: u64,
+    #[serde(rename = "b")]
+    bids: Vec<(Decimal, Decimal)>, // (price, qty)
+    #[serde(rename = "a")]
+    asks: Vec<(Decimal, Decimal)>, // (price, qty)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AggTrade {
+    #[serde(rename = "p")]
+    pub price: Decimal,
+    #[serde(rename = "q")]
+    pub qty: Decimal,
+    /// Trade time in milliseconds
+    #[serde(rename = "T")]
+    pub time: u64,
+    /// The seller was the taker, i.e. the trade hit a bid
+    #[serde(rename = "m")]
+    pub is_buyer_maker: bool,
+}
+
+impl AggTrade {
+    /// Side of the book the trade was made against
+    fn maker_side(&self) -> OrderType {
+        if self.is_buyer_maker { OrderType::Bid } else { OrderType::Ask }
+    }
+}
+
+/// Volume traded at a price level within the kept trades against the largest
+/// size the level ever showed in the book meanwhile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelFlow {
+    pub side: OrderType,
+    pub price: Decimal,
+    pub traded: Decimal,
+    pub max_visible: Decimal,
+    pub trades: usize,
+}
+
+impl LevelFlow {
+    /// How many times the largest visible size traded at the level
+    pub fn hidden_ratio(&self) -> Option<Decimal> {
+        (!self.max_visible.is_zero()).then(|| self.traded / self.max_visible)
+    }
+
+    /// Much more traded than was ever visible, the level is hidden or replenished.
+    /// Levels never seen in the book are skipped, they were placed and taken between updates.
+    fn is_iceberg(&self) -> bool {
+        self.trades >= ICEBERG_MIN_TRADES && self.hidden_ratio().is_some_and(|ratio| ratio >= ICEBERG_RATIO)
+    }
+}
+
+/// Iceberg levels of a locally maintained book.
+#[derive(Debug, Clone)]
+pub struct Icebergs {
+    pub last_price: Decimal,
+    /// Seconds of trades the levels were found in
+    pub window_secs: u64,
+    pub levels: Vec<LevelFlow>,
+}
+
+fn apply_levels(side: &mut BTreeMap<Decimal, Decimal>, levels: &[(Decimal, Decimal)]) {
+    for (price, qty) in levels {
+        if qty.is_zero() {
+            side.remove(price);
+        } else {
+            side.insert(*price, *qty);
+        }
+    }
+}
+
+/// Order book continued from a REST snapshot with the depth diff stream.
+#[derive(Debug, Default)]
+struct LocalOrderBook {
+    last_update_id: u64,
+    asks: BTreeMap<Decimal, Decimal>,
+    bids: BTreeMap<Decimal, Decimal>,
+    /// Farthest ask and bid of the snapshot, levels beyond them are only known if they changed since
+    asks_boundary: Option<Decimal>,
+    bids_boundary: Option<Decimal>,
+    last_price: Option<Decimal>,
+    trades: VecDeque<AggTrade>,
+    /// Time in seconds since which all trades are kept
+    trades_since: u64,
+    /// Levels traded within the kept trades
+    flows: HashMap<(OrderType, Decimal), LevelFlow>,
+}
+
+impl LocalOrderBook {
+    fn from_snapshot(snapshot: DepthSnapshot) -> Self {
+        let to_levels = |entities: Vec<OrderBookEntity>| -> BTreeMap<Decimal, Decimal> {
+            entities
+                .into_iter()
+                .map(|entity| (entity.price, entity.qty))
+                .collect()
+        };
+
+        let asks = to_levels(snapshot.book.asks);
+        let bids = to_levels(snapshot.book.bids);
+
+        LocalOrderBook {
+            last_update_id: snapshot.last_update_id,
+            asks_boundary: asks.last_key_value().map(|(price, _)| *price),
+            bids_boundary: bids.first_key_value().map(|(price, _)| *price),
+            asks,
+            bids,
+            last_price: None,
+            trades: VecDeque::new(),
+            trades_since: unix_now(),
+            flows: HashMap::new(),
+        }
+    }
+
+    /// Updates already included in the book are skipped. An update starting after
+    /// the next expected id means events were lost and the book has to be rebuilt.
+    fn apply(&mut self, update: &DepthUpdate) -> Result<()> {
+        if update.final_update_id <= self.last_update_id {
+            return Ok(());
+        }
+
+        if update.first_update_id > self.last_update_id + 1 {
+            return Err(ServiceError::internal(format!(
+                "Depth update gap: expected {}, received {}",
+                self.last_update_id + 1,
+                update.first_update_id
+            )));
+        }
+
+        apply_levels(&mut self.asks, &update.asks);
+        apply_levels(&mut self.bids, &update.bids);
+        self.last_update_id = update.final_update_id;
+
+        // a price crossed by the market shows up on both sides, each is compared with its own trades
+        let asks = update.asks.iter().map(|level| (OrderType::Ask, level));
+        let bids = update.bids.iter().map(|level| (OrderType::Bid, level));
+
+        for (side, (price, qty)) in asks.chain(bids) {
+            if let Some(flow) = self.flows.get_mut(&(side, *price)) {
+                flow.max_visible = flow.max_visible.max(*qty);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, event: &StreamEvent) -> Result<()> {
+        match event {
+            StreamEvent::DepthUpdate(update) => self.apply(update),
+            StreamEvent::AggTrade(trade) => {
+                self.last_price = Some(trade.price);
+                self.record_trade(trade.clone());
+                Ok(())
+            }
+        }
+    }
+
+    fn record_trade(&mut self, trade: AggTrade) {
+        let expired = (trade.time / 1000).saturating_sub(TRADES_TTL_SECS);
+
+        while self.trades.front().is_some_and(|oldest| oldest.time / 1000 < expired) {
+            let Some(oldest) = self.trades.pop_front() else {
+                break;
+            };
+            let key = (oldest.maker_side(), oldest.price);
+
+            if let Some(flow) = self.flows.get_mut(&key) {
+                flow.traded -= oldest.qty;
+                flow.trades -= 1;
+
+                if flow.trades == 0 {
+                    self.flows.remove(&key);
+                }
+            }
+        }
+
+        // the book still shows the size the trade was made against
+        let side = trade.maker_side();
+        let visible = match side {
+            OrderType::Ask => self.asks.get(&trade.price),
+            OrderType::Bid => self.bids.get(&trade.price),
+        };
+
+        let flow = self.flows.entry((side, trade.price)).or_insert_with(|| LevelFlow {
+            side,
+            price: trade.price,
+            traded: Decimal::ZERO,
+            max_visible: Decimal::ZERO,
+            trades: 0,
+        });
+        flow.max_visible = flow.max_visible.max(visible.copied().unwrap_or_default());
+        flow.traded += trade.qty;
+        flow.trades += 1;
+
+        self.trades_since = self.trades_since.max(expired);
+        self.trades.push_back(trade);
+    }
+
+    /// Iceberg levels with the most volume traded first
+    fn icebergs(&self) -> Vec<LevelFlow> {
+        let mut levels = self
+            .flows
+            .values()
+            .filter(|flow| flow.is_iceberg())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        levels.sort_by_key(|flow| std::cmp::Reverse(flow.traded * flow.price));
+        levels.truncate(ICEBERGS_LIMIT);
+        levels
+    }
+
+    /// `None` if some of the trades since that time were not kept.
+    fn trades_since(&self, since: u64) -> Option<Vec<AggTrade>> {
+        if since < self.trades_since {
+            return None;
+        }
+
+        let trades = self
+            .trades
+            .iter()
+            .filter(|trade| trade.time / 1000 >= since)
+            .cloned()
+            .collect();
+
+        Some(trades)
+    }
+
+    /// Levels up to the snapshot boundaries, so coverage and gaps aren't computed over
+    /// the sparse levels the stream added beyond them.
+    fn to_order_book(&self) -> OrderBook {
+        let to_entity = |(price, qty): (&Decimal, &Decimal)| OrderBookEntity::new(*price, *qty);
+        let asks_boundary = self.asks_boundary.unwrap_or(Decimal::MAX);
+        let bids_boundary = self.bids_boundary.unwrap_or(Decimal::MIN);
+
+        OrderBook {
+            asks: self.asks.range(..=asks_boundary).map(to_entity).collect(),
+            bids: self.bids.range(bids_boundary..).rev().map(to_entity).collect(),
+        }
+    }
+}
+
+fn parse_message(
+    message: Option<std::result::Result<Message, tungstenite::Error>>,
+) -> Result<Option<StreamEvent>> {
+    match message {
+        None => Err(ServiceError::internal("Depth stream closed".to_string())),
+        Some(Err(e)) => Err(ServiceError::from(e)),
+        Some(Ok(Message::Text(text))) => serde_json::from_str::<StreamMessage>(text.as_str())
+            .map(|message| Some(message.data))
+            .map_err(ServiceError::from),
+        Some(Ok(_)) => Ok(None),
+    }
+}
+
+async fn next_event<S>(ws: &mut S) -> Result<Option<StreamEvent>>
+where
+    S: Stream<Item = std::result::Result<Message, tungstenite::Error>> + Unpin,
+{
+    let message = timeout(READ_TIMEOUT, ws.next())
+        .await
+        .map_err(|_| ServiceError::internal("Depth stream timed out".to_string()))?;
+    parse_message(message)
+}
+
+/// Binance spot order books kept in memory for a set of hot symbols.
+/// A book is only present while it is in sync with the stream.
+#[derive(Default)]
+pub struct LocalOrderBooks {
+    books: RwLock<HashMap<String, LocalOrderBook>>,
+}
+
+impl LocalOrderBooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Last trade price and order book of the symbol, if it is maintained locally.
+    pub async fn get(&self, symbol: &str) -> Option<(Decimal, OrderBook)> {
+        let books = self.books.read().await;
+        let book = books.get(symbol)?;
+
+        book.last_price.map(|last_price| (last_price, book.to_order_book()))
+    }
+
+    /// Trades of the symbol since the given time in seconds, if all of them were
+    /// received from the stream.
+    pub async fn get_trades_since(&self, symbol: &str, since: u64) -> Option<Vec<AggTrade>> {
+        self.books.read().await.get(symbol)?.trades_since(since)
+    }
+
+    /// Iceberg levels of the symbol, if it is maintained locally.
+    pub async fn get_icebergs(&self, symbol: &str) -> Option<Icebergs> {
+        let books = self.books.read().await;
+        let book = books.get(symbol)?;
+
+        book.last_price.map(|last_price| Icebergs {
+            last_price,
+            window_secs: unix_now().saturating_sub(book.trades_since),
+            levels: book.icebergs(),
+        })
+    }
+
+    async fn follow(&self, binance: &Binance, stream_url: &str, symbol: &str) -> Result<()> {
+        let stream = symbol.to_lowercase();
+        let url = format!("{}/stream?streams={}@depth@100ms/{}@aggTrade", stream_url, stream, stream);
+        let (mut ws, _) = connect_async(url).await?;
+
+        // events received while the snapshot is requested are applied on top of it
+        let snapshot = binance.get_depth_snapshot(symbol);
+        tokio::pin!(snapshot);
+        let mut buffered = Vec::new();
+
+        let snapshot = loop {
+            tokio::select! {
+                snapshot = &mut snapshot => break snapshot?,
+                event = next_event(&mut ws) => buffered.extend(event?),
+            }
+        };
+
+        let mut book = LocalOrderBook::from_snapshot(snapshot);
+        for event in buffered.iter() {
+            book.handle(event)?;
+        }
+
+        self.books.write().await.insert(symbol.to_string(), book);
+        info!("Local order book for {} is in sync", symbol);
+
+        loop {
+            let Some(event) = next_event(&mut ws).await? else {
+                continue;
+            };
+
+            let mut books = self.books.write().await;
+            let book = books
+                .get_mut(symbol)
+                .ok_or_else(|| ServiceError::internal(format!("Local order book for {} is missing", symbol)))?;
+
+            book.handle(&event)?;
+        }
+    }
+}
+
+/// Snapshots are requested through the same client as the REST handlers, so their
+/// weight counts against the same limit.
+pub async fn maintain_local_order_books(
+    books: Arc<LocalOrderBooks>,
+    symbols: Vec<String>,
+    binance: Arc<Binance>,
+    stream_url: String,
+) {
+    let tasks = symbols.into_iter().map(|symbol| {
+        let symbol = symbol.to_uppercase();
+        let books = books.clone();
+        let binance = binance.as_ref();
+        let stream_url = stream_url.as_str();
+
+        async move {
+            loop {
+                if let Err(e) = books.follow(binance, stream_url, &symbol).await {
+                    error!("Local order book for {} is out of sync: {}", symbol, e);
+                }
+
+                books.books.write().await.remove(&symbol);
+                sleep(RECONNECT_DELAY).await;
+            }
+        }
+    });
+
+    join_all(tasks).await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snapshot() -> DepthSnapshot {
+        DepthSnapshot {
+            last_update_id: 100,
+            book: OrderBook::default(),
+        }
+    }
+
+    fn update(first_update_id: u64, final_update_id: u64) -> DepthUpdate {
+        DepthUpdate {
+            first_update_id,
+            final_update_id,
+            bids: vec![(Decimal::from(90), Decimal::ZERO), (Decimal::from(95), Decimal::ONE)],
+            asks: vec![(Decimal::from(150), Decimal::TWO)],
+        }
+    }
+
+    #[test]
+    fn test_apply_skips_stale_updates() {
+        let mut book = LocalOrderBook::from_snapshot(snapshot());
+
+        let result = book.apply(&update(90, 100));
+        assert!(result.is_ok());
+        assert_eq!(book.last_update_id, 100);
+        assert_eq!(book.bids.get(&Decimal::from(90)), Some(&Decimal::TEN));
+    }
+
+    #[test]
+    fn test_apply_overlapping_and_sequential_updates() {
+        let mut book = LocalOrderBook::from_snapshot(snapshot());
+
+        book.apply(&update(95, 105)).unwrap();
+        assert_eq!(book.last_update_id, 105);
+        assert_eq!(book.bids.get(&Decimal::from(90)), None);
+        assert_eq!(book.bids.get(&Decimal::from(95)), Some(&Decimal::ONE));
+        assert_eq!(book.asks.get(&Decimal::from(150)), Some(&Decimal::TWO));
+
+        book.apply(&update(106, 110)).unwrap();
+        assert_eq!(book.last_update_id, 110);
+    }
+
+    #[test]
+    fn test_apply_detects_gap() {
+        let mut book = LocalOrderBook::from_snapshot(snapshot());
+
+        let result = book.apply(&update(102, 110));
+        assert!(result.is_err());
+        assert_eq!(book.last_update_id, 100);
+    }
+
+    #[test]
+    fn test_to_order_book() {
+        let mut book = LocalOrderBook::from_snapshot(snapshot());
+        book.apply(&update(101, 101)).unwrap();
+
+        let result = book.to_order_book();
+
+        let ask_prices = result.asks.iter().map(|entity| entity.price).collect::<Vec<_>>();
+        let expected = [100, 150, 200, 250].map(Decimal::from).to_vec();
+        assert_eq!(ask_prices, expected);
+
+        let bid_prices = result.bids.iter().map(|entity| entity.price).collect::<Vec<_>>();
+        let expected = [95, 85, 80, 75].map(Decimal::from).to_vec();
+        assert_eq!(bid_prices, expected);
+    }
+
+    #[test]
+    fn test_to_order_book_stops_at_snapshot_boundary() {
+        let mut book = LocalOrderBook::from_snapshot(snapshot());
+        book.apply(&DepthUpdate {
+            first_update_id: 101,
+            final_update_id: 101,
+            bids: vec![(Decimal::from(70), Decimal::ONE)],
+            asks: vec![(Decimal::from(300), Decimal::ONE)],
+        })
+        .unwrap();
+
+        let result = book.to_order_book();
+        assert_eq!(result.asks.last().map(|entity| entity.price), Some(Decimal::from(250)));
+        assert_eq!(result.bids.last().map(|entity| entity.price), Some(Decimal::from(75)));
+    }
+
+    #[test]
+    fn test_parse_stream_event() {
+        let text = r#"{"stream":"solusdt@depth@100ms","data":{"e":"depthUpdate","E":1,"s":"SOLUSDT","U":157,"u":160,"b":[["0.0024","10"]],"a":[["0.0026","100"]]}}"#;
+        let message = Some(Ok(Message::text(text)));
+
+        match parse_message(message).unwrap() {
+            Some(StreamEvent::DepthUpdate(update)) => {
+                assert_eq!(update.first_update_id, 157);
+                assert_eq!(update.final_update_id, 160);
+                assert_eq!(update.bids, vec![(Decimal::new(24, 4), Decimal::TEN)]);
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_trades_since() {
+        let mut book = LocalOrderBook::from_snapshot(snapshot());
+        book.trades_since = 1000;
+
+        let trade = |secs: u64| AggTrade {
+            price: Decimal::ONE_HUNDRED,
+            qty: Decimal::ONE,
+            time: secs * 1000,
+            is_buyer_maker: false,
+        };
+
+        book.record_trade(trade(1010));
+        book.record_trade(trade(1020));
+        assert_eq!(book.trades_since(1015), Some(vec![trade(1020)]));
+        assert_eq!(book.trades_since(990), None);
+
+        // the first trade expires
+        book.record_trade(trade(1015 + TRADES_TTL_SECS));
+        assert_eq!(book.trades.len(), 2);
+        assert_eq!(book.trades_since(1010), None);
+        assert_eq!(book.trades_since(1015).map(|trades| trades.len()), Some(2));
+    }
+
+    #[test]
+    fn test_icebergs() {
+        let mut book = LocalOrderBook::from_snapshot(snapshot());
+        let sell = |price: i64, qty: i64| AggTrade {
+            price: Decimal::from(price),
+            qty: Decimal::from(qty),
+            time: 0,
+            is_buyer_maker: true,
+        };
+
+        // the bid at 90 shows 10 and is refilled after every hit, 80 is hit once for all of its 10
+        for _ in 0..3 {
+            book.record_trade(sell(90, 10));
+            book.apply(&DepthUpdate {
+                first_update_id: book.last_update_id + 1,
+                final_update_id: book.last_update_id + 1,
+                bids: vec![(Decimal::from(90), Decimal::TEN)],
+                asks: vec![],
+            })
+            .unwrap();
+        }
+        book.record_trade(sell(80, 10));
+
+        let icebergs = book.icebergs();
+        assert_eq!(icebergs.len(), 1);
+
+        let iceberg = &icebergs[0];
+        assert_eq!((iceberg.side, iceberg.price), (OrderType::Bid, Decimal::from(90)));
+        assert_eq!((iceberg.traded, iceberg.max_visible, iceberg.trades), (Decimal::from(30), Decimal::TEN, 3));
+        assert_eq!(iceberg.hidden_ratio(), Some(Decimal::from(3)));
+
+        // the refills expire along with the trades
+        book.record_trade(sell(100, 1));
+        book.record_trade(AggTrade { time: (TRADES_TTL_SECS + 1) * 1000, ..sell(100, 1) });
+        assert!(book.icebergs().is_empty());
+        assert_eq!(book.flows.len(), 1);
+    }
+
+    #[test]
+    fn test_icebergs_keep_sides_apart() {
+        let mut book = LocalOrderBook::from_snapshot(snapshot());
+        let trade = |is_buyer_maker: bool| AggTrade {
+            price: Decimal::from(90),
+            qty: Decimal::TEN,
+            time: 0,
+            is_buyer_maker,
+        };
+
+        // the bid at 90 shows 10 and is hit once, then the price drops through it
+        // and the asks placed at 90 are lifted three times for their whole size
+        book.record_trade(trade(true));
+        book.apply(&DepthUpdate {
+            first_update_id: 101,
+            final_update_id: 101,
+            bids: vec![(Decimal::from(90), Decimal::ZERO)],
+            asks: vec![(Decimal::from(90), Decimal::from(30))],
+        })
+        .unwrap();
+
+        for _ in 0..3 {
+            book.record_trade(trade(false));
+        }
+
+        assert!(book.icebergs().is_empty());
+
+        let bid = &book.flows[&(OrderType::Bid, Decimal::from(90))];
+        assert_eq!((bid.traded, bid.max_visible, bid.trades), (Decimal::TEN, Decimal::TEN, 1));
+
+        let ask = &book.flows[&(OrderType::Ask, Decimal::from(90))];
+        assert_eq!((ask.traded, ask.max_visible, ask.trades), (Decimal::from(30), Decimal::from(30), 3));
+    }
+}
@@ -1,10 +1,17 @@
 mod binance;
+mod bybit;
 mod config;
 mod error;
+mod exchange;
+mod local_book;
+mod okx;
 mod order_book;
+mod rate_limit;
 mod redis;
 mod state;
 mod telegram;
+mod time;
+mod wall_tracker;
 
 use std::sync::Arc;
 
@@ -15,20 +22,48 @@ use teloxide::prelude::*;
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
 use teloxide::utils::command::BotCommands;
 
+use crate::binance::Binance;
 use crate::config::ServiceConfig;
-use crate::state::{AppState, periodic_exchange_info_update};
-use crate::telegram::format_message;
+use crate::exchange::{Market, OrderBookSource};
+use crate::local_book::{LocalOrderBooks, maintain_local_order_books};
+use crate::order_book::{Grouping, WallSort};
+use crate::state::{AppState, periodic_exchange_info_update, track_walls};
+use crate::telegram::{
+    format_comparison, format_error, format_icebergs, format_impact, format_message, format_tracked_walls,
+};
+use crate::time::unix_now;
 
 type MyDialogue = Dialogue<State, InMemStorage<State>>;
 type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
 
+/// Smaller `/impact` sizes buy next to nothing and only show rounding noise
+const MIN_IMPACT_SIZE: Decimal = Decimal::ONE;
+
 #[derive(Clone, Default)]
 pub enum State {
     #[default]
     Start,
     ReceiveToken,
+    ReceiveMarket {
+        token: String,
+    },
+    ReceiveSource {
+        token: String,
+    },
     ReceiveFilters {
         token: String,
+        source: OrderBookSource,
+    },
+    ReceiveGrouping {
+        token: String,
+        source: OrderBookSource,
+        depth: Decimal,
+    },
+    ReceiveSorting {
+        token: String,
+        source: OrderBookSource,
+        depth: Decimal,
+        grouping: Grouping,
     },
 }
 
@@ -38,6 +73,20 @@ enum Command {
     Help,
     Start,
     Cancel,
+    #[command(
+        description = "compare spot and futures walls, e.g. /compare SOL 5%",
+        parse_with = "split"
+    )]
+    Compare { token: String, depth: String },
+    #[command(
+        description = "estimate slippage of a market order in USD, e.g. /impact SOL 50000",
+        parse_with = "split"
+    )]
+    Impact { token: String, size: String },
+    #[command(description = "show how long walls of a watched coin have been standing, e.g. /walls SOL")]
+    Walls { token: String },
+    #[command(description = "show levels refilled beyond their visible size of a hot coin, e.g. /icebergs SOL")]
+    Icebergs { token: String },
 }
 
 fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>> {
@@ -46,7 +95,11 @@ fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>>
     let command_handler = teloxide::filter_command::<Command, _>()
         .branch(case![State::Start].branch(case![Command::Start].endpoint(start)))
         .branch(case![Command::Help].endpoint(help))
-        .branch(case![Command::Cancel].endpoint(cancel));
+        .branch(case![Command::Cancel].endpoint(cancel))
+        .branch(case![Command::Compare { token, depth }].endpoint(compare))
+        .branch(case![Command::Impact { token, size }].endpoint(impact))
+        .branch(case![Command::Walls { token }].endpoint(walls))
+        .branch(case![Command::Icebergs { token }].endpoint(icebergs));
 
     let message_handler = Update::filter_message()
         .branch(command_handler)
@@ -55,7 +108,11 @@ fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>>
         .branch(dptree::endpoint(invalid_state));
 
     let callback_query_handler = Update::filter_callback_query()
-        .branch(case![State::ReceiveFilters { token }].endpoint(perform));
+        .branch(case![State::ReceiveMarket { token }].endpoint(receive_market))
+        .branch(case![State::ReceiveSource { token }].endpoint(receive_source))
+        .branch(case![State::ReceiveFilters { token, source }].endpoint(receive_depth))
+        .branch(case![State::ReceiveGrouping { token, source, depth }].endpoint(receive_grouping))
+        .branch(case![State::ReceiveSorting { token, source, depth, grouping }].endpoint(perform));
 
     dialogue::enter::<Update, InMemStorage<State>, State, _>()
         .branch(message_handler)
@@ -68,9 +125,26 @@ async fn main() {
     let config = ServiceConfig::read_from_file().expect("Failed to read config");
 
     let bot = Bot::new(config.telegram_token);
-    let app_state = Arc::new(AppState::new(config.redis_url, config.allowed_users));
+    let local_books = Arc::new(LocalOrderBooks::new());
+    // shared with the local books so their snapshots count against the same weight limit
+    let binance_spot = Arc::new(Binance::new(&config.binance));
+    let app_state = Arc::new(AppState::new(
+        config.redis_url,
+        config.allowed_users,
+        &config.binance,
+        binance_spot.clone(),
+        local_books.clone(),
+    ));
 
     let exch_info_update_handler = tokio::spawn(periodic_exchange_info_update(app_state.clone()));
+    let local_books_handler = tokio::spawn(maintain_local_order_books(
+        local_books,
+        config.hot_symbols,
+        binance_spot,
+        config.binance.stream_url,
+    ));
+
+    let wall_tracking_handler = tokio::spawn(track_walls(app_state.clone(), config.wall_tracking));
 
     let dispatcher_handler = tokio::spawn(async move {
         Dispatcher::builder(bot, schema())
@@ -83,7 +157,12 @@ async fn main() {
             .await;
     });
 
-    if let Err(e) = tokio::try_join!(exch_info_update_handler, dispatcher_handler) {
+    if let Err(e) = tokio::try_join!(
+        exch_info_update_handler,
+        local_books_handler,
+        wall_tracking_handler,
+        dispatcher_handler
+    ) {
         log::error!("Something went wrong: {:?}", e);
     }
 }
@@ -91,7 +170,7 @@ async fn main() {
 async fn start(bot: Bot, dialogue: MyDialogue, msg: Message, app_state: Arc<AppState>) -> HandlerResult {
     match app_state.authorize(msg.chat.id).await {
         Ok(_) => {
-            bot.send_message(msg.chat.id, "Enter Binance spot token").await?;
+            bot.send_message(msg.chat.id, "Enter token").await?;
             dialogue.update(State::ReceiveToken).await?
         },
         Err(e) => {
@@ -124,6 +203,137 @@ async fn cancel(bot: Bot, dialogue: MyDialogue, msg: Message, app_state: Arc<App
     Ok(())
 }
 
+/// Replies to the user and returns `None` if they are not allowed or the token is not a known symbol
+async fn authorized_symbol(
+    bot: &Bot,
+    msg: &Message,
+    app_state: &AppState,
+    token: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    if let Err(e) = app_state.authorize(msg.chat.id).await {
+        bot.send_message(msg.chat.id, e.to_string()).await?;
+        return Ok(None);
+    }
+
+    match app_state.validate_symbol(token).await {
+        Ok(symbol) => Ok(Some(symbol)),
+        Err(e) => {
+            bot.send_message(msg.chat.id, format!("{} ❌", e)).await?;
+            Ok(None)
+        }
+    }
+}
+
+async fn compare(
+    bot: Bot,
+    msg: Message,
+    (token, depth): (String, String),
+    app_state: Arc<AppState>,
+) -> HandlerResult {
+    let Some(symbol) = authorized_symbol(&bot, &msg, &app_state, &token).await? else {
+        return Ok(());
+    };
+
+    let depth = match depth.trim_end_matches('%').parse::<Decimal>() {
+        Ok(depth) if depth > Decimal::ZERO && depth < Decimal::ONE_HUNDRED => depth,
+        _ => {
+            bot.send_message(msg.chat.id, "Depth should be a percentage, e.g. 5%").await?;
+            return Ok(());
+        }
+    };
+
+    let msg_text = match app_state.get_spot_futures_comparison(symbol.clone(), depth).await {
+        Ok((spot, futures)) => format_comparison(spot, futures),
+        Err(e) => {
+            if e.is_unexpected() {
+                log::error!("Error while comparing order books for {}: {}", symbol, e);
+            }
+            format_error(&e)
+        }
+    };
+
+    bot.send_message(msg.chat.id, msg_text)
+        .parse_mode(ParseMode::MarkdownV2)
+        .await?;
+
+    Ok(())
+}
+
+async fn impact(
+    bot: Bot,
+    msg: Message,
+    (token, size): (String, String),
+    app_state: Arc<AppState>,
+) -> HandlerResult {
+    let Some(symbol) = authorized_symbol(&bot, &msg, &app_state, &token).await? else {
+        return Ok(());
+    };
+
+    let size = match size.trim_start_matches('$').replace(',', "").parse::<Decimal>() {
+        Ok(size) if size >= MIN_IMPACT_SIZE => size,
+        _ => {
+            bot.send_message(msg.chat.id, "Size should be a USD amount of at least $1, e.g. 50000").await?;
+            return Ok(());
+        }
+    };
+
+    let msg_text = match app_state.get_market_impact(symbol.clone(), size).await {
+        Ok(estimate) => format_impact(estimate),
+        Err(e) => {
+            if e.is_unexpected() {
+                log::error!("Error while estimating market impact for {}: {}", symbol, e);
+            }
+            format_error(&e)
+        }
+    };
+
+    bot.send_message(msg.chat.id, msg_text)
+        .parse_mode(ParseMode::MarkdownV2)
+        .await?;
+
+    Ok(())
+}
+
+async fn walls(bot: Bot, msg: Message, token: String, app_state: Arc<AppState>) -> HandlerResult {
+    let Some(symbol) = authorized_symbol(&bot, &msg, &app_state, &token).await? else {
+        return Ok(());
+    };
+
+    match app_state.get_tracked_walls(&symbol).await {
+        Some(walls) => {
+            bot.send_message(msg.chat.id, format_tracked_walls(&symbol, walls, unix_now()))
+                .parse_mode(ParseMode::MarkdownV2)
+                .await?;
+        }
+        None => {
+            let text = format!("Walls of {} are not tracked, add it to wall_tracking in the config", symbol);
+            bot.send_message(msg.chat.id, text).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn icebergs(bot: Bot, msg: Message, token: String, app_state: Arc<AppState>) -> HandlerResult {
+    let Some(symbol) = authorized_symbol(&bot, &msg, &app_state, &token).await? else {
+        return Ok(());
+    };
+
+    match app_state.get_icebergs(&symbol).await {
+        Some(icebergs) => {
+            bot.send_message(msg.chat.id, format_icebergs(&symbol, icebergs))
+                .parse_mode(ParseMode::MarkdownV2)
+                .await?;
+        }
+        None => {
+            let text = format!("Trades of {} are not followed, add it to hot_symbols in the config", symbol);
+            bot.send_message(msg.chat.id, text).await?;
+        }
+    }
+
+    Ok(())
+}
+
 async fn invalid_state(bot: Bot, msg: Message) -> HandlerResult {
     bot.send_message(
         msg.chat.id,
@@ -139,17 +349,19 @@ async fn receive_token(
     msg: Message,
     app_state: Arc<AppState>,
 ) -> HandlerResult {
-    match msg.text() {
-        Some(token) => match app_state.validate_symbol(token).await {
+    if let Some(token) = msg.text() {
+        match app_state.validate_symbol(token).await {
             Ok(validated) => {
-                let options = ["3%", "5%", "8%", "10%", "15%"]
-                    .map(|product| InlineKeyboardButton::callback(product, product));
+                let options = [Market::Spot, Market::Futures].map(|market| {
+                    let market = market.to_string();
+                    InlineKeyboardButton::callback(market.clone(), market)
+                });
 
-                bot.send_message(msg.chat.id, format!("{} ✅\nChoose depth", validated))
+                bot.send_message(msg.chat.id, format!("{} ✅\nChoose market", validated))
                     .reply_markup(InlineKeyboardMarkup::new([options]))
                     .await?;
                 dialogue
-                    .update(State::ReceiveFilters { token: validated })
+                    .update(State::ReceiveMarket { token: validated })
                     .await?;
             }
             Err(e) => {
@@ -157,46 +369,176 @@ async fn receive_token(
                 bot.send_message(msg.chat.id, err_msg).await?;
                 dialogue.update(State::ReceiveToken).await?
             }
-        },
-        None => {}
+        }
     }
 
     Ok(())
 }
 
-async fn perform(
+async fn receive_market(
     bot: Bot,
     dialogue: MyDialogue,
     token: String,
     query: CallbackQuery,
     app_state: Arc<AppState>,
+) -> HandlerResult {
+    let parsed_query = query.data.as_deref().map(str::parse::<Market>);
+
+    if let Some(Ok(market)) = parsed_query {
+        let chat_id = query.chat_id().unwrap();
+
+        match (market, app_state.get_listing_exchanges(&token, market).await) {
+            (Market::Spot, Ok(exchanges)) => {
+                let mut sources = exchanges
+                    .into_iter()
+                    .map(OrderBookSource::Exchange)
+                    .collect::<Vec<_>>();
+
+                if sources.len() > 1 {
+                    sources.push(OrderBookSource::Aggregated);
+                }
+
+                let options = sources.iter().map(|source| {
+                    let source = source.to_string();
+                    InlineKeyboardButton::callback(source.clone(), source)
+                });
+
+                bot.send_message(chat_id, format!("{} • {}\nChoose exchange", token, market))
+                    .reply_markup(InlineKeyboardMarkup::new([options]))
+                    .await?;
+                dialogue.update(State::ReceiveSource { token }).await?;
+            }
+            (Market::Futures, Ok(exchanges)) => {
+                let source = OrderBookSource::Exchange(exchanges[0]);
+                send_depth_options(&bot, chat_id, &token, source).await?;
+                dialogue
+                    .update(State::ReceiveFilters { token, source })
+                    .await?;
+            }
+            (_, Err(e)) => {
+                let err_msg = format!("Try again. {} ❌", e);
+                bot.send_message(chat_id, err_msg).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn send_depth_options(
+    bot: &Bot,
+    chat_id: ChatId,
+    token: &str,
+    source: OrderBookSource,
+) -> HandlerResult {
+    let options = ["3%", "5%", "8%", "10%", "15%"]
+        .map(|product| InlineKeyboardButton::callback(product, product));
+
+    bot.send_message(chat_id, format!("{} • {}\nChoose depth", token, source))
+        .reply_markup(InlineKeyboardMarkup::new([options]))
+        .await?;
+
+    Ok(())
+}
+
+async fn receive_source(
+    bot: Bot,
+    dialogue: MyDialogue,
+    token: String,
+    query: CallbackQuery,
+) -> HandlerResult {
+    let parsed_query = query.data.as_deref().map(str::parse::<OrderBookSource>);
+
+    if let Some(Ok(source)) = parsed_query {
+        send_depth_options(&bot, query.chat_id().unwrap(), &token, source).await?;
+        dialogue
+            .update(State::ReceiveFilters { token, source })
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn receive_depth(
+    bot: Bot,
+    dialogue: MyDialogue,
+    (token, source): (String, OrderBookSource),
+    query: CallbackQuery,
 ) -> HandlerResult {
     let parsed_query = query.clone().data.map(|mut depth| {
         depth.pop();
         depth.parse::<Decimal>()
     });
 
-    match parsed_query {
-        Some(Ok(depth)) => {
-            let order_book = app_state
-                .get_filtered_order_book(token.clone(), depth)
-                .await;
-
-            let msg = match order_book {
-                Ok(order_book) => format_message(order_book),
-                Err(e) => {
-                    log::error!("Error while requesting order book for {}: {}", token, e);
-                    "Something went wrong. Try again later".to_string()
+    if let Some(Ok(depth)) = parsed_query {
+        let options = ["Levels", "0.1%", "0.5%", "1%", "10 ticks"]
+            .map(|grouping| InlineKeyboardButton::callback(grouping, grouping));
+
+        bot.send_message(query.chat_id().unwrap(), format!("{} • {} • {}%\nGroup levels by", token, source, depth))
+            .reply_markup(InlineKeyboardMarkup::new([options]))
+            .await?;
+        dialogue
+            .update(State::ReceiveGrouping { token, source, depth })
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn receive_grouping(
+    bot: Bot,
+    dialogue: MyDialogue,
+    (token, source, depth): (String, OrderBookSource, Decimal),
+    query: CallbackQuery,
+) -> HandlerResult {
+    let parsed_query = query.data.as_deref().map(str::parse::<Grouping>);
+
+    if let Some(Ok(grouping)) = parsed_query {
+        let options = [WallSort::Notional, WallSort::Score].map(|sort| {
+            let sort = sort.to_string();
+            InlineKeyboardButton::callback(sort.clone(), sort)
+        });
+
+        bot.send_message(query.chat_id().unwrap(), format!("{} • {} • {}%\nRank walls by", token, source, depth))
+            .reply_markup(InlineKeyboardMarkup::new([options]))
+            .await?;
+        dialogue
+            .update(State::ReceiveSorting { token, source, depth, grouping })
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn perform(
+    bot: Bot,
+    dialogue: MyDialogue,
+    (token, source, depth, grouping): (String, OrderBookSource, Decimal, Grouping),
+    query: CallbackQuery,
+    app_state: Arc<AppState>,
+) -> HandlerResult {
+    let parsed_query = query.data.as_deref().map(str::parse::<WallSort>);
+
+    if let Some(Ok(sort)) = parsed_query {
+        let order_book = app_state
+            .get_filtered_order_book(token.clone(), source, depth, grouping, sort)
+            .await;
+
+        let msg = match order_book {
+            Ok(order_book) => format_message(order_book),
+            Err(e) => {
+                if e.is_unexpected() {
+                    log::error!("Error while requesting {} order book for {}: {}", source, token, e);
                 }
-            };
+                format_error(&e)
+            }
+        };
 
-            bot.send_message(query.chat_id().unwrap(), msg)
-                .parse_mode(ParseMode::MarkdownV2)
-                .await?;
+        bot.send_message(query.chat_id().unwrap(), msg)
+            .parse_mode(ParseMode::MarkdownV2)
+            .await?;
 
-            dialogue.update(State::ReceiveToken).await.unwrap()
-        }
-        _ => {}
+        dialogue.update(State::ReceiveToken).await.unwrap()
     }
 
     Ok(())
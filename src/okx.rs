@@ -0,0 +1,226 @@
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde::de::IgnoredAny;
+
+use crate::error::{Result, ServiceError};
+use crate::exchange::{Exchange, ExchangeId, Precision, SymbolInfo};
+use crate::order_book::{OrderBook, OrderBookEntity};
+
+const INSTRUMENTS_URL: &str = "https://www.okx.com/api/v5/public/instruments";
+const ORDER_BOOK_URL: &str = "https://www.okx.com/api/v5/market/books-full";
+const TICKER_URL: &str = "https://www.okx.com/api/v5/market/ticker";
+const INST_TYPE: &str = "SPOT";
+const ORDER_BOOK_DEPTH: &str = "5000"; // maximum available depth for books-full
+const QUOTE_ASSET: &str = "USDT";
+
+/// OKX identifies instruments as `BASE-QUOTE`, the rest of the bot uses `BASEQUOTE`.
+fn to_inst_id(symbol: &str) -> String {
+    match symbol.strip_suffix(QUOTE_ASSET) {
+        Some(base) => format!("{}-{}", base, QUOTE_ASSET),
+        None => symbol.to_string(),
+    }
+}
+
+fn from_inst_id(inst_id: &str) -> String {
+    inst_id.replace('-', "")
+}
+
+#[derive(Debug, Deserialize)]
+struct OkxResponse<T> {
+    code: String,
+    msg: String,
+    data: Vec<T>,
+}
+
+impl<T> OkxResponse<T> {
+    fn into_result(self) -> Result<Vec<T>> {
+        match self.code.as_str() {
+            "0" => Ok(self.data),
+            code => Err(ServiceError::internal(format!("OKX error {}: {}", code, self.msg))),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OkxTicker {
+    #[allow(unused)]
+    inst_id: String,
+    last: Decimal,
+}
+
+// (price, qty, deprecated liquidated orders, number of orders)
+type OkxLevel = (Decimal, Decimal, IgnoredAny, IgnoredAny);
+
+#[derive(Debug, Deserialize)]
+struct OkxOrderBookResponse {
+    bids: Vec<OkxLevel>,
+    asks: Vec<OkxLevel>,
+}
+
+impl From<OkxOrderBookResponse> for OrderBook {
+    fn from(value: OkxOrderBookResponse) -> Self {
+        let to_entity = |entity: Vec<OkxLevel>| -> Vec<OrderBookEntity> {
+            entity
+                .into_iter()
+                .map(|(price, qty, _, _)| OrderBookEntity::new(price, qty))
+                .collect()
+        };
+
+        let asks = to_entity(value.asks);
+        let bids = to_entity(value.bids);
+
+        OrderBook { asks, bids }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OkxInstrument {
+    inst_id: String,
+    state: String,
+    tick_sz: Option<Decimal>,
+    lot_sz: Option<Decimal>,
+}
+
+impl From<OkxInstrument> for SymbolInfo {
+    fn from(value: OkxInstrument) -> Self {
+        let precision = value
+            .tick_sz
+            .zip(value.lot_sz)
+            .map(|(tick_size, step_size)| Precision { tick_size, step_size });
+
+        SymbolInfo {
+            symbol: from_inst_id(&value.inst_id),
+            is_trading: value.state == "live",
+            precision,
+        }
+    }
+}
+
+pub struct Okx {
+    client: Client,
+}
+
+impl Okx {
+    pub fn new() -> Self {
+        Self {
+            client: Client::new(),
+        }
+    }
+
+    fn request_with_params(&self, url: &str, params: &[(&str, &str)]) -> RequestBuilder {
+        self.client.get(url).query(params)
+    }
+
+    async fn send_request<T: for<'a> Deserialize<'a>>(&self, request: RequestBuilder) -> Result<Vec<T>> {
+        request
+            .send()
+            .await?
+            .json::<OkxResponse<T>>()
+            .await?
+            .into_result()
+    }
+
+    async fn send_single_request<T: for<'a> Deserialize<'a>>(&self, request: RequestBuilder) -> Result<T> {
+        self.send_request::<T>(request)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| ServiceError::internal("OKX returned no data".to_string()))
+    }
+}
+
+#[async_trait]
+impl Exchange for Okx {
+    fn id(&self) -> ExchangeId {
+        ExchangeId::Okx
+    }
+
+    async fn get_last_price(&self, symbol: &str) -> Result<Decimal> {
+        let inst_id = to_inst_id(symbol);
+        let req = self.request_with_params(TICKER_URL, &[("instId", &inst_id)]);
+
+        let resp = self.send_single_request::<OkxTicker>(req).await?;
+
+        Ok(resp.last)
+    }
+
+    async fn get_order_book(&self, symbol: &str) -> Result<OrderBook> {
+        let inst_id = to_inst_id(symbol);
+        let params = &[("instId", inst_id.as_str()), ("sz", ORDER_BOOK_DEPTH)];
+        let req = self.request_with_params(ORDER_BOOK_URL, params);
+
+        let resp = self.send_single_request::<OkxOrderBookResponse>(req).await?;
+
+        Ok(resp.into())
+    }
+
+    async fn get_exchange_info(&self) -> Result<Vec<SymbolInfo>> {
+        let req = self.request_with_params(INSTRUMENTS_URL, &[("instType", INST_TYPE)]);
+
+        let resp = self
+            .send_request::<OkxInstrument>(req)
+            .await?
+            .into_iter()
+            .map(SymbolInfo::from)
+            .collect();
+
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inst_id_conversion() {
+        assert_eq!(to_inst_id("SOLUSDT"), "SOL-USDT");
+        assert_eq!(from_inst_id("SOL-USDT"), "SOLUSDT");
+    }
+
+    #[test]
+    fn test_parse_order_book() {
+        let data = r#"{"code":"0","msg":"","data":[{"asks":[["101.5","2","0","3"]],"bids":[["99.5","4","0","1"]],"ts":"1"}]}"#;
+
+        let resp = serde_json::from_str::<OkxResponse<OkxOrderBookResponse>>(data)
+            .unwrap()
+            .into_result()
+            .unwrap()
+            .remove(0);
+
+        let expected = OrderBook {
+            asks: vec![OrderBookEntity::new(Decimal::new(1015, 1), Decimal::TWO)],
+            bids: vec![OrderBookEntity::new(Decimal::new(995, 1), Decimal::from(4))],
+        };
+
+        assert_eq!(OrderBook::from(resp), expected);
+    }
+
+    #[ignore]
+    #[tokio::test]
+    async fn test_get_last_price() {
+        let okx = Okx::new();
+        let result = okx.get_last_price("SOLUSDT").await;
+        println!("Result: {:?}", result);
+    }
+
+    #[ignore]
+    #[tokio::test]
+    async fn test_get_order_book() {
+        let okx = Okx::new();
+        let result = okx.get_order_book("SOLUSDT").await;
+        println!("Result: {:?}", result);
+    }
+
+    #[ignore]
+    #[tokio::test]
+    async fn test_get_exchange_info() {
+        let okx = Okx::new();
+        let result = okx.get_exchange_info().await;
+        println!("Result: {:?}", result);
+    }
+}
@@ -1,20 +1,91 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
 use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
 use serde::{Deserialize, Serialize};
 
+use crate::error::{Result, ServiceError};
+use crate::exchange::{ExchangeId, OrderBookSource, Precision};
+
 const TOP_LIMITS: usize = 10;
+/// Distances from price in percent the cumulative liquidity is reported for,
+/// also bounds of the bands imbalance is broken down by
+const CUMULATIVE_DEPTH_PERCENTS: [u32; 3] = [1, 2, 5];
+/// Imbalance beyond which one side is considered dominant
+const IMBALANCE_THRESHOLD: Decimal = Decimal::from_parts(2, 0, 0, false, 1);
+/// Number of equal slices the requested depth is split into when looking for gaps
+const GAP_SLICES: u32 = 10;
+/// Slices holding less than this share of the average slice notional are gaps
+const GAP_THRESHOLD: Decimal = Decimal::from_parts(2, 0, 0, false, 1);
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct OrderBookEntity {
     pub price: Decimal,
     pub qty: Decimal,
+    /// Source exchange, only set for levels of an aggregated order book.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exchange: Option<ExchangeId>,
+}
+
+impl OrderBookEntity {
+    pub fn new(price: Decimal, qty: Decimal) -> Self {
+        OrderBookEntity {
+            price,
+            qty,
+            exchange: None,
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct OrderBook {
     pub asks: Vec<OrderBookEntity>,
     pub bids: Vec<OrderBookEntity>,
 }
 
+impl OrderBook {
+    pub fn with_exchange(mut self, exchange: ExchangeId) -> Self {
+        self.asks.iter_mut().chain(self.bids.iter_mut()).for_each(|entity| {
+            entity.exchange = Some(exchange);
+        });
+        self
+    }
+}
+
+/// Merges order books of several exchanges into one, keeping asks ascending
+/// and bids descending by price. Levels with equal prices are summed up.
+pub fn merge_order_books(books: Vec<OrderBook>) -> OrderBook {
+    let mut asks = BTreeMap::new();
+    let mut bids = BTreeMap::new();
+
+    for book in books {
+        merge_levels(&mut asks, book.asks);
+        merge_levels(&mut bids, book.bids);
+    }
+
+    OrderBook {
+        asks: asks.into_values().collect(),
+        bids: bids.into_values().rev().collect(),
+    }
+}
+
+fn merge_levels(merged: &mut BTreeMap<Decimal, OrderBookEntity>, levels: Vec<OrderBookEntity>) {
+    for entity in levels {
+        merged
+            .entry(entity.price)
+            .and_modify(|level| {
+                level.qty += entity.qty;
+                // a level quoted on several exchanges has no single source
+                if level.exchange != entity.exchange {
+                    level.exchange = None;
+                }
+            })
+            .or_insert(entity);
+    }
+}
+
 #[cfg(test)]
 impl OrderBook {
     pub fn asks() -> Vec<OrderBookEntity> {
@@ -22,18 +93,22 @@ impl OrderBook {
             OrderBookEntity {
                 price: Decimal::ONE_HUNDRED,
                 qty: Decimal::ONE,
+                exchange: None,
             },
             OrderBookEntity {
                 price: Decimal::from(150),
                 qty: Decimal::TEN,
+                exchange: None,
             },
             OrderBookEntity {
                 price: Decimal::from(200),
                 qty: Decimal::TWO,
+                exchange: None,
             },
             OrderBookEntity {
                 price: Decimal::from(250),
                 qty: Decimal::ONE,
+                exchange: None,
             },
         ]
     }
@@ -43,18 +118,22 @@ impl OrderBook {
             OrderBookEntity {
                 price: Decimal::from(90),
                 qty: Decimal::TEN,
+                exchange: None,
             },
             OrderBookEntity {
                 price: Decimal::from(85),
                 qty: Decimal::ONE_HUNDRED,
+                exchange: None,
             },
             OrderBookEntity {
                 price: Decimal::from(80),
                 qty: Decimal::TWO,
+                exchange: None,
             },
             OrderBookEntity {
                 price: Decimal::from(75),
                 qty: Decimal::ONE,
+                exchange: None,
             },
         ]
     }
@@ -67,27 +146,119 @@ impl OrderBook {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum OrderType {
     Ask,
     Bid,
 }
 
+/// How levels are combined before picking the top walls, so a wall split
+/// across adjacent ticks is reported as one zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    Levels,
+    /// Buckets as wide as the given percent of the last price
+    Percent(Decimal),
+    Ticks(u32),
+}
+
+impl Grouping {
+    /// Width of a bucket in quote currency, `None` if levels are not grouped
+    pub fn bucket_size(&self, last_price: Decimal, precision: Option<Precision>) -> Option<Decimal> {
+        let size = match self {
+            Grouping::Levels => return None,
+            Grouping::Percent(percent) => round_price(last_price * percent / Decimal::ONE_HUNDRED, precision),
+            Grouping::Ticks(ticks) => precision?.tick_size * Decimal::from(*ticks),
+        };
+
+        (!size.is_zero()).then(|| size.normalize())
+    }
+}
+
+impl Display for Grouping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Grouping::Levels => write!(f, "Levels"),
+            Grouping::Percent(percent) => write!(f, "{}%", percent),
+            Grouping::Ticks(ticks) => write!(f, "{} ticks", ticks),
+        }
+    }
+}
+
+impl FromStr for Grouping {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let unknown = || ServiceError::internal(format!("Unknown grouping {}", s));
+
+        if s == "Levels" {
+            Ok(Grouping::Levels)
+        } else if let Some(percent) = s.strip_suffix('%') {
+            percent.parse().map(Grouping::Percent).map_err(|_| unknown())
+        } else if let Some(ticks) = s.strip_suffix(" ticks") {
+            ticks.parse().map(Grouping::Ticks).map_err(|_| unknown())
+        } else {
+            Err(unknown())
+        }
+    }
+}
+
 pub struct ExtendedOrderBook {
     pub symbol: String,
-    pub asks: Vec<OrderBookEntity>,
-    pub bids: Vec<OrderBookEntity>,
+    pub source: OrderBookSource,
+    pub asks: Vec<Wall>,
+    pub bids: Vec<Wall>,
     pub last_price: Decimal,
     pub depth: Decimal,
+    /// Prices actually covered by the fetched book, `None` if the side is empty
+    pub asks_range: Option<PriceRange>,
+    pub bids_range: Option<PriceRange>,
+    /// Tick and step size of the symbol, `None` if the exchange didn't report them
+    pub precision: Option<Precision>,
+    pub grouping: Grouping,
+    /// Width of the price buckets levels were grouped into, `None` for single levels
+    pub bucket_size: Option<Decimal>,
+    pub sort: WallSort,
+    pub cumulative_depth: Vec<CumulativeDepth>,
+    /// Notional of the whole requested depth split by distance from price
+    pub depth_bands: Vec<DepthBand>,
+    pub asks_gaps: Vec<LiquidityGap>,
+    pub bids_gaps: Vec<LiquidityGap>,
 }
 
 impl ExtendedOrderBook {
+    pub fn rounded_last_price(&self) -> Decimal {
+        round_price(self.last_price, self.precision)
+    }
+
+    pub fn asks_covered_depth(&self) -> Decimal {
+        self.asks_range
+            .map(|range| range.depth(self.last_price))
+            .unwrap_or_default()
+    }
+
+    pub fn bids_covered_depth(&self) -> Decimal {
+        self.bids_range
+            .map(|range| range.depth(self.last_price))
+            .unwrap_or_default()
+    }
+
+    /// Notional of every level within the requested depth, not only the top walls
+    pub fn total_band(&self) -> DepthBand {
+        DepthBand {
+            from: Decimal::ZERO,
+            to: self.depth,
+            asks: self.depth_bands.iter().map(|band| band.asks).sum(),
+            bids: self.depth_bands.iter().map(|band| band.bids).sum(),
+        }
+    }
+
     pub fn asks_volume(&self) -> Decimal {
-        self.asks.iter().map(|item| item.qty).sum()
+        self.asks.iter().map(|wall| wall.level.notional).sum()
     }
 
     pub fn bids_volume(&self) -> Decimal {
-        self.bids.iter().map(|item| item.qty).sum()
+        self.bids.iter().map(|wall| wall.level.notional).sum()
     }
 }
 
@@ -102,41 +273,507 @@ fn find_border_price(last_price: Decimal, depth: Decimal, order_type: OrderType)
     last_price * percentage
 }
 
+/// Prices of the nearest and the farthest level of one side of a book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceRange {
+    pub nearest: Decimal,
+    pub farthest: Decimal,
+}
+
+impl PriceRange {
+    /// Distance in percent from the last price to the farthest level.
+    pub fn depth(&self, last_price: Decimal) -> Decimal {
+        if last_price.is_zero() {
+            return Decimal::ZERO;
+        }
+
+        (self.farthest - last_price).abs() / last_price * Decimal::ONE_HUNDRED
+    }
+}
+
+pub fn find_covered_range(book: &[OrderBookEntity], order_type: OrderType) -> Option<PriceRange> {
+    let prices = book.iter().map(|entity| entity.price);
+    let (lowest, highest) = (prices.clone().min()?, prices.max()?);
+
+    let range = match order_type {
+        OrderType::Ask => PriceRange { nearest: lowest, farthest: highest },
+        OrderType::Bid => PriceRange { nearest: highest, farthest: lowest },
+    };
+
+    Some(range)
+}
+
+pub fn round_price(price: Decimal, precision: Option<Precision>) -> Decimal {
+    match precision {
+        Some(precision) => precision.round_price(price),
+        None => price.trunc_with_scale(5).normalize(),
+    }
+}
+
+/// Total notional of both sides within `percent` of the last price.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CumulativeDepth {
+    pub percent: Decimal,
+    pub asks: Decimal,
+    pub bids: Decimal,
+}
+
+/// Both sides of a book rounded to the precision and trimmed once to the farthest
+/// distance any metric of an answer looks at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrimmedOrderBook {
+    pub asks: Vec<Level>,
+    pub bids: Vec<Level>,
+}
+
+impl TrimmedOrderBook {
+    fn side(&self, order_type: OrderType) -> &[Level] {
+        match order_type {
+            OrderType::Ask => &self.asks,
+            OrderType::Bid => &self.bids,
+        }
+    }
+}
+
+/// Trims the book to the requested depth or the farthest cumulative depth, whichever is larger.
+pub fn trim_order_book(
+    book: OrderBook,
+    last_price: Decimal,
+    depth: Decimal,
+    precision: Option<Precision>,
+) -> TrimmedOrderBook {
+    let farthest = CUMULATIVE_DEPTH_PERCENTS.map(Decimal::from).into_iter().fold(depth, Decimal::max);
+    let trim = |entities, order_type| {
+        let border_price = find_border_price(last_price, farthest, order_type);
+        trim_order_book_entity(entities, border_price, order_type, precision)
+    };
+
+    TrimmedOrderBook {
+        asks: trim(book.asks, OrderType::Ask),
+        bids: trim(book.bids, OrderType::Bid),
+    }
+}
+
+/// Levels within `percent` of the last price
+fn levels_within(
+    levels: &[Level],
+    last_price: Decimal,
+    percent: Decimal,
+    order_type: OrderType,
+) -> impl Iterator<Item = &Level> {
+    let border_price = find_border_price(last_price, percent, order_type);
+
+    levels.iter().filter(move |level| match order_type {
+        OrderType::Ask => level.price <= border_price,
+        OrderType::Bid => level.price >= border_price,
+    })
+}
+
+fn side_notional(book: &TrimmedOrderBook, last_price: Decimal, percent: Decimal, order_type: OrderType) -> Decimal {
+    levels_within(book.side(order_type), last_price, percent, order_type)
+        .map(|level| level.notional)
+        .sum()
+}
+
+/// Cumulative liquidity within ±1%, ±2% and ±5% of the last price.
+pub fn cumulative_depth(book: &TrimmedOrderBook, last_price: Decimal) -> Vec<CumulativeDepth> {
+    CUMULATIVE_DEPTH_PERCENTS
+        .map(Decimal::from)
+        .into_iter()
+        .map(|percent| CumulativeDepth {
+            percent,
+            asks: side_notional(book, last_price, percent, OrderType::Ask),
+            bids: side_notional(book, last_price, percent, OrderType::Bid),
+        })
+        .collect()
+}
+
+/// Outcome of a market order walking one side of a book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MarketImpact {
+    /// Notional actually filled, less than the order size if the book ran out
+    pub filled: Decimal,
+    pub average_price: Decimal,
+    pub worst_price: Decimal,
+    /// Distance in percent from the last price to the average fill price
+    pub slippage: Decimal,
+    pub exhausted: bool,
+}
+
+/// Market buy (walking asks) or sell (walking bids) of `size` USD. Levels are
+/// expected best first, as exchanges return them. `None` if the side is empty or
+/// the size is too small to buy any of the base asset.
+pub fn market_impact(book: &[OrderBookEntity], size: Decimal, last_price: Decimal) -> Option<MarketImpact> {
+    let mut remaining = size;
+    let mut base_qty = Decimal::ZERO;
+    let mut worst_price = None;
+
+    for entity in book.iter().filter(|entity| !entity.price.is_zero()) {
+        if remaining.is_zero() {
+            break;
+        }
+
+        let notional = (entity.price * entity.qty).min(remaining);
+        base_qty += notional / entity.price;
+        remaining -= notional;
+        worst_price = Some(entity.price);
+    }
+
+    let worst_price = worst_price?;
+    if base_qty.is_zero() {
+        return None;
+    }
+
+    let filled = size - remaining;
+    let average_price = filled / base_qty;
+
+    let slippage = if last_price.is_zero() {
+        Decimal::ZERO
+    } else {
+        (average_price - last_price).abs() / last_price * Decimal::ONE_HUNDRED
+    };
+
+    Some(MarketImpact {
+        filled,
+        average_price,
+        worst_price,
+        slippage,
+        exhausted: !remaining.is_zero(),
+    })
+}
+
+/// Market buy and sell estimates of the same size on the first spot exchange listing the symbol.
+pub struct ImpactEstimate {
+    pub symbol: String,
+    pub exchange: ExchangeId,
+    pub size: Decimal,
+    pub last_price: Decimal,
+    pub precision: Option<Precision>,
+    pub buy: Option<MarketImpact>,
+    pub sell: Option<MarketImpact>,
+}
+
+/// Notional of both sides between `from` and `to` percent from the last price.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthBand {
+    pub from: Decimal,
+    pub to: Decimal,
+    pub asks: Decimal,
+    pub bids: Decimal,
+}
+
+impl DepthBand {
+    /// Share of bids minus share of asks, from -1 with only asks to 1 with only bids
+    pub fn imbalance(&self) -> Decimal {
+        let total = self.asks + self.bids;
+
+        if total.is_zero() {
+            return Decimal::ZERO;
+        }
+
+        (self.bids - self.asks) / total
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self.imbalance() {
+            imbalance if imbalance > IMBALANCE_THRESHOLD => "bullish",
+            imbalance if imbalance < -IMBALANCE_THRESHOLD => "bearish",
+            _ => "neutral",
+        }
+    }
+}
+
+/// Splits the requested depth into bands bounded by ±1%, ±2% and ±5%.
+pub fn depth_bands(book: &TrimmedOrderBook, last_price: Decimal, depth: Decimal) -> Vec<DepthBand> {
+    let mut bounds = CUMULATIVE_DEPTH_PERCENTS
+        .map(Decimal::from)
+        .into_iter()
+        .filter(|percent| *percent < depth)
+        .collect::<Vec<_>>();
+    bounds.push(depth);
+
+    let mut from = CumulativeDepth {
+        percent: Decimal::ZERO,
+        asks: Decimal::ZERO,
+        bids: Decimal::ZERO,
+    };
+
+    bounds
+        .into_iter()
+        .map(|percent| {
+            let to = CumulativeDepth {
+                percent,
+                asks: side_notional(book, last_price, percent, OrderType::Ask),
+                bids: side_notional(book, last_price, percent, OrderType::Bid),
+            };
+
+            let band = DepthBand {
+                from: from.percent,
+                to: to.percent,
+                asks: to.asks - from.asks,
+                bids: to.bids - from.bids,
+            };
+
+            from = to;
+            band
+        })
+        .collect()
+}
+
+/// Level of a processed book, sized both in the base asset and in USD.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Level {
+    pub price: Decimal,
+    pub base_qty: Decimal,
+    pub notional: Decimal,
+    pub exchange: Option<ExchangeId>,
+}
+
+impl From<OrderBookEntity> for Level {
+    fn from(value: OrderBookEntity) -> Self {
+        Level {
+            price: value.price,
+            base_qty: value.qty,
+            notional: value.qty * value.price,
+            exchange: value.exchange,
+        }
+    }
+}
+
 fn trim_order_book_entity(
     book: Vec<OrderBookEntity>,
     border_price: Decimal,
     order_type: OrderType,
-) -> Vec<OrderBookEntity> {
+    precision: Option<Precision>,
+) -> Vec<Level> {
     book.into_iter()
         .filter(|entry| match order_type {
             OrderType::Ask => entry.price <= border_price,
             OrderType::Bid => entry.price >= border_price,
         })
         .map(|entity| {
-            let price = entity.price.trunc_with_scale(5).normalize();
-            OrderBookEntity {
-                price,
-                qty: entity.qty * price
-            }
+            let price = round_price(entity.price, precision);
+            let qty = precision.map_or(entity.qty, |precision| precision.round_qty(entity.qty));
+            Level::from(OrderBookEntity { price, qty, ..entity })
         })
         .collect()
 }
 
-fn sort_and_filter(mut book: Vec<OrderBookEntity>) -> Vec<OrderBookEntity> {
-    // sorting by quantity from the biggest one to the smallest one
-    book.sort_by(|book1, book2| book2.qty.cmp(&book1.qty));
+/// Sums up levels falling into the same `bucket_size` wide price range. Each bucket
+/// is reported at its lower bound with the total size of its levels.
+fn group_order_book_entity(book: Vec<Level>, bucket_size: Decimal) -> Vec<Level> {
+    let mut buckets: BTreeMap<Decimal, Level> = BTreeMap::new();
+
+    for level in book {
+        let price = ((level.price / bucket_size).floor() * bucket_size).normalize();
+
+        buckets
+            .entry(price)
+            .and_modify(|bucket| {
+                bucket.base_qty += level.base_qty;
+                bucket.notional += level.notional;
+                // a bucket with levels of several exchanges has no single source
+                if bucket.exchange != level.exchange {
+                    bucket.exchange = None;
+                }
+            })
+            .or_insert(Level { price, ..level });
+    }
+
+    buckets.into_values().collect()
+}
+
+/// How much a wall stands out from the liquidity around it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WallScore {
+    /// Notional relative to the median level of the side within the depth
+    pub size_vs_median: Decimal,
+    /// Share of the side notional within the depth, in percent
+    pub side_share: Decimal,
+    /// Distance from the last price, in percent
+    pub distance: Decimal,
+    /// Size vs median, boosted by the side share (×1.5 for half of the side) and
+    /// discounted by distance down to a half at the depth border
+    pub score: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wall {
+    pub level: Level,
+    pub score: WallScore,
+    /// Notional of the levels between the last price and the wall
+    pub notional_before: Decimal,
+    /// Walls at this price were pulled before, see `WallTracker`
+    pub suspected_spoof: bool,
+}
+
+/// What the top walls are ranked by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WallSort {
+    Notional,
+    Score,
+}
+
+impl Display for WallSort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let val = match self {
+            WallSort::Notional => "Notional",
+            WallSort::Score => "Score",
+        };
+
+        write!(f, "{}", val)
+    }
+}
+
+impl FromStr for WallSort {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "Notional" => Ok(WallSort::Notional),
+            "Score" => Ok(WallSort::Score),
+            _ => Err(ServiceError::internal(format!("Unknown sorting {}", s))),
+        }
+    }
+}
+
+fn median(mut values: Vec<Decimal>) -> Decimal {
+    values.sort();
+
+    match values.len() {
+        0 => Decimal::ZERO,
+        len if len % 2 == 0 => (values[len / 2 - 1] + values[len / 2]) / Decimal::TWO,
+        len => values[len / 2],
+    }
+}
+
+fn score_walls(mut book: Vec<Level>, last_price: Decimal, depth: Decimal) -> Vec<Wall> {
+    let median = median(book.iter().map(|level| level.notional).collect());
+    let side_volume = book.iter().map(|level| level.notional).sum::<Decimal>();
+
+    // grouped levels come ascending by price, bids have to be walked from the top
+    book.sort_by_key(|level| (level.price - last_price).abs());
+    let mut notional_before = Decimal::ZERO;
+
+    // divisions by zero give a zero component
+    let ratio = |value: Decimal, base: Decimal| {
+        if base.is_zero() { Decimal::ZERO } else { value / base }
+    };
+
+    book.into_iter()
+        .map(|level| {
+            let size_vs_median = ratio(level.notional, median);
+            let distance = ratio((level.price - last_price).abs(), last_price) * Decimal::ONE_HUNDRED;
+            let discount = Decimal::ONE - ratio(distance, depth * Decimal::TWO);
+            let share = ratio(level.notional, side_volume);
+
+            let score = WallScore {
+                size_vs_median,
+                side_share: share * Decimal::ONE_HUNDRED,
+                distance,
+                score: size_vs_median * (Decimal::ONE + share) * discount.max(Decimal::ZERO),
+            };
+
+            let wall = Wall { score, notional_before, level, suspected_spoof: false };
+            notional_before += wall.level.notional;
+            wall
+        })
+        .collect()
+}
+
+fn sort_and_filter(mut book: Vec<Wall>, sort: WallSort) -> Vec<Wall> {
+    // sorting from the biggest one to the smallest one
+    match sort {
+        WallSort::Notional => book.sort_by_key(|wall| std::cmp::Reverse(wall.level.notional)),
+        WallSort::Score => book.sort_by_key(|wall| std::cmp::Reverse(wall.score.score)),
+    }
+
     book.into_iter().take(TOP_LIMITS).collect()
 }
 
+/// Price range within the requested depth with unusually thin liquidity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiquidityGap {
+    pub range: PriceRange,
+    pub notional: Decimal,
+}
+
+/// Splits the requested depth into equal slices and reports runs of slices
+/// whose notional is far below the average one. Slices beyond `covered`, the
+/// range of the fetched book, are not reported, as nothing is known about them.
+pub fn find_liquidity_gaps(
+    book: &TrimmedOrderBook,
+    covered: Option<PriceRange>,
+    last_price: Decimal,
+    depth: Decimal,
+    order_type: OrderType,
+) -> Vec<LiquidityGap> {
+    let covered = match covered {
+        Some(covered) if !last_price.is_zero() && !depth.is_zero() => covered,
+        _ => return Vec::new(),
+    };
+
+    let slice_depth = depth / Decimal::from(GAP_SLICES);
+    let mut slices = vec![Decimal::ZERO; GAP_SLICES as usize];
+
+    for level in levels_within(book.side(order_type), last_price, depth, order_type) {
+        let distance = (level.price - last_price).abs() / last_price * Decimal::ONE_HUNDRED;
+        let index = (distance / slice_depth).floor().to_usize().unwrap_or_default();
+        slices[index.min(GAP_SLICES as usize - 1)] += level.notional;
+    }
+
+    let threshold = slices.iter().sum::<Decimal>() / Decimal::from(GAP_SLICES) * GAP_THRESHOLD;
+    let slice_price = |index: usize| find_border_price(last_price, slice_depth * Decimal::from(index), order_type);
+
+    let mut gaps: Vec<LiquidityGap> = Vec::new();
+    let mut previous_gap = false;
+
+    for (index, notional) in slices.into_iter().enumerate() {
+        let nearest = slice_price(index);
+        let beyond_book = match order_type {
+            OrderType::Ask => nearest >= covered.farthest,
+            OrderType::Bid => nearest <= covered.farthest,
+        };
+
+        let is_gap = notional < threshold && !beyond_book;
+        let farthest = slice_price(index + 1);
+
+        match gaps.last_mut() {
+            Some(gap) if is_gap && previous_gap => {
+                gap.range.farthest = farthest;
+                gap.notional += notional;
+            }
+            _ if is_gap => gaps.push(LiquidityGap {
+                range: PriceRange { nearest, farthest },
+                notional,
+            }),
+            _ => {}
+        }
+
+        previous_gap = is_gap;
+    }
+
+    gaps
+}
+
 pub fn process_order_book_entity(
-    book: Vec<OrderBookEntity>,
+    book: &TrimmedOrderBook,
     last_price: Decimal,
     depth: Decimal,
     order_type: OrderType,
-) -> Vec<OrderBookEntity> {
-    let border_price = find_border_price(last_price, depth, order_type);
-    let entities = trim_order_book_entity(book, border_price, order_type);
-    sort_and_filter(entities)
+    bucket_size: Option<Decimal>,
+    sort: WallSort,
+) -> Vec<Wall> {
+    let entities = levels_within(book.side(order_type), last_price, depth, order_type)
+        .cloned()
+        .collect();
+
+    let entities = match bucket_size {
+        Some(bucket_size) => group_order_book_entity(entities, bucket_size),
+        None => entities,
+    };
+
+    sort_and_filter(score_walls(entities, last_price, depth), sort)
 }
 
 
@@ -144,6 +781,17 @@ pub fn process_order_book_entity(
 mod test {
     use super::*;
 
+    fn levels(book: Vec<OrderBookEntity>) -> Vec<Level> {
+        book.into_iter().map(Level::from).collect()
+    }
+
+    fn trimmed(book: OrderBook) -> TrimmedOrderBook {
+        TrimmedOrderBook {
+            asks: levels(book.asks),
+            bids: levels(book.bids),
+        }
+    }
+
     #[test]
     fn test_find_border_price() {
         let last_price = Decimal::from(200);
@@ -158,38 +806,72 @@ mod test {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_find_covered_range() {
+        let last_price = Decimal::ONE_HUNDRED;
+
+        let result = find_covered_range(&OrderBook::asks(), OrderType::Ask).unwrap();
+        let expected = PriceRange {
+            nearest: Decimal::ONE_HUNDRED,
+            farthest: Decimal::from(250),
+        };
+        assert_eq!(result, expected);
+        assert_eq!(result.depth(last_price), Decimal::from(150));
+
+        let result = find_covered_range(&OrderBook::bids(), OrderType::Bid).unwrap();
+        let expected = PriceRange {
+            nearest: Decimal::from(90),
+            farthest: Decimal::from(75),
+        };
+        assert_eq!(result, expected);
+        assert_eq!(result.depth(last_price), Decimal::from(25));
+
+        let result = find_covered_range(&[], OrderType::Bid);
+        assert_eq!(result, None);
+    }
+
     #[test]
     fn test_trim_order_book() {
         let bids = OrderBook::bids();
         let asks = OrderBook::asks();
 
-        let result = trim_order_book_entity(bids, Decimal::from(83), OrderType::Bid);
+        let result = trim_order_book_entity(bids, Decimal::from(83), OrderType::Bid, None);
         let expected = vec![
-            OrderBookEntity {
+            Level {
                 price: Decimal::from(90),
-                qty: Decimal::from(900),
+                base_qty: Decimal::TEN,
+                notional: Decimal::from(900),
+                exchange: None,
             },
-            OrderBookEntity {
+            Level {
                 price: Decimal::from(85),
-                qty: Decimal::from(8500),
+                base_qty: Decimal::ONE_HUNDRED,
+                notional: Decimal::from(8500),
+                exchange: None,
             },
         ];
 
         assert_eq!(result, expected);
 
-        let result = trim_order_book_entity(asks, Decimal::from(200), OrderType::Ask);
+        let result = trim_order_book_entity(asks, Decimal::from(200), OrderType::Ask, None);
         let expected = vec![
-            OrderBookEntity {
+            Level {
                 price: Decimal::ONE_HUNDRED,
-                qty: Decimal::ONE_HUNDRED,
+                base_qty: Decimal::ONE,
+                notional: Decimal::ONE_HUNDRED,
+                exchange: None,
             },
-            OrderBookEntity {
+            Level {
                 price: Decimal::from(150),
-                qty: Decimal::from(1500),
+                base_qty: Decimal::TEN,
+                notional: Decimal::from(1500),
+                exchange: None,
             },
-            OrderBookEntity {
+            Level {
                 price: Decimal::from(200),
-                qty: Decimal::from(400),
+                base_qty: Decimal::TWO,
+                notional: Decimal::from(400),
+                exchange: None,
             },
         ];
 
@@ -197,29 +879,270 @@ mod test {
     }
 
     #[test]
-    fn test_sort_and_filter() {
-        let entity = OrderBook::bids();
+    fn test_trim_order_book_with_precision() {
+        // sub-cent prices collapse to zero when truncated to 5 decimals
+        let bids = vec![
+            OrderBookEntity::new(Decimal::new(1034, 8), Decimal::from(1_000_000)),
+            OrderBookEntity::new(Decimal::new(1033, 8), Decimal::from(2_000_000)),
+        ];
+        let precision = Precision {
+            tick_size: Decimal::new(1, 8),
+            step_size: Decimal::ONE,
+        };
+
+        let result = trim_order_book_entity(bids, Decimal::new(1, 8), OrderType::Bid, Some(precision));
+        let prices = result.iter().map(|entity| entity.price).collect::<Vec<_>>();
+        assert_eq!(prices, vec![Decimal::new(1034, 8), Decimal::new(1033, 8)]);
+        assert_eq!(result[0].base_qty, Decimal::from(1_000_000));
+        assert_eq!(result[0].notional, Decimal::new(1034, 2));
+    }
+
+    #[test]
+    fn test_group_order_book() {
+        let bids = levels(vec![
+            OrderBookEntity::new(Decimal::new(1005, 1), Decimal::from(300)),
+            OrderBookEntity::new(Decimal::new(1003, 1), Decimal::from(200)),
+            OrderBookEntity::new(Decimal::new(1001, 1), Decimal::from(100)),
+            OrderBookEntity::new(Decimal::new(995, 1), Decimal::from(50)),
+        ]);
 
-        let result = sort_and_filter(entity);
+        let result = group_order_book_entity(bids, Decimal::ONE);
         let expected = vec![
-            OrderBookEntity {
-                price: Decimal::from(85),
-                qty: Decimal::ONE_HUNDRED,
+            Level {
+                price: Decimal::from(99),
+                base_qty: Decimal::from(50),
+                notional: Decimal::from(4975),
+                exchange: None,
             },
-            OrderBookEntity {
-                price: Decimal::from(90),
-                qty: Decimal::TEN,
+            Level {
+                price: Decimal::from(100),
+                base_qty: Decimal::from(600),
+                notional: Decimal::from(60220),
+                exchange: None,
             },
-            OrderBookEntity {
-                price: Decimal::from(80),
-                qty: Decimal::TWO,
+        ];
+        assert_eq!(result, expected);
+
+        let mixed = OrderBook {
+            asks: vec![],
+            bids: vec![
+                OrderBookEntity::new(Decimal::new(1011, 1), Decimal::ONE),
+                OrderBookEntity::new(Decimal::new(1002, 1), Decimal::ONE),
+            ],
+        }
+        .with_exchange(ExchangeId::Bybit);
+
+        let mut bids = mixed.bids;
+        bids.push(OrderBookEntity {
+            price: Decimal::new(1001, 1),
+            qty: Decimal::ONE,
+            exchange: Some(ExchangeId::Binance),
+        });
+
+        let result = group_order_book_entity(levels(bids), Decimal::ONE);
+        assert_eq!(result[0].exchange, None);
+        assert_eq!(result[1].exchange, Some(ExchangeId::Bybit));
+    }
+
+    #[test]
+    fn test_grouping_bucket_size() {
+        let precision = Precision {
+            tick_size: Decimal::new(1, 2),
+            step_size: Decimal::new(1, 3),
+        };
+        let last_price = Decimal::new(14652, 2);
+
+        assert_eq!(Grouping::Levels.bucket_size(last_price, Some(precision)), None);
+        assert_eq!(
+            Grouping::Percent(Decimal::new(1, 1)).bucket_size(last_price, Some(precision)),
+            Some(Decimal::new(14, 2))
+        );
+        assert_eq!(
+            Grouping::Ticks(10).bucket_size(last_price, Some(precision)),
+            Some(Decimal::new(1, 1))
+        );
+        assert_eq!(Grouping::Ticks(10).bucket_size(last_price, None), None);
+        assert_eq!("0.5%".parse::<Grouping>().unwrap(), Grouping::Percent(Decimal::new(5, 1)));
+        assert_eq!("10 ticks".parse::<Grouping>().unwrap(), Grouping::Ticks(10));
+    }
+
+    #[test]
+    fn test_cumulative_depth() {
+        let book = OrderBook {
+            asks: vec![
+                OrderBookEntity::new(Decimal::from(100), Decimal::ONE),
+                OrderBookEntity::new(Decimal::from(101), Decimal::ONE),
+                OrderBookEntity::new(Decimal::from(104), Decimal::TWO),
+                OrderBookEntity::new(Decimal::from(110), Decimal::TEN),
+            ],
+            bids: vec![
+                OrderBookEntity::new(Decimal::from(99), Decimal::TEN),
+                OrderBookEntity::new(Decimal::from(97), Decimal::ONE),
+            ],
+        };
+
+        let result = cumulative_depth(&trimmed(book), Decimal::ONE_HUNDRED);
+        let expected = vec![
+            CumulativeDepth {
+                percent: Decimal::ONE,
+                asks: Decimal::from(201),
+                bids: Decimal::from(990),
             },
-            OrderBookEntity {
-                price: Decimal::from(75),
-                qty: Decimal::ONE,
+            CumulativeDepth {
+                percent: Decimal::TWO,
+                asks: Decimal::from(201),
+                bids: Decimal::from(990),
+            },
+            CumulativeDepth {
+                percent: Decimal::from(5),
+                asks: Decimal::from(409),
+                bids: Decimal::from(1087),
             },
         ];
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_market_impact() {
+        let last_price = Decimal::from(99);
+
+        // 2250 USD available in total
+        let result = market_impact(&OrderBook::asks(), Decimal::from(1000), last_price).unwrap();
+        assert_eq!(result.filled, Decimal::from(1000));
+        assert_eq!(result.worst_price, Decimal::from(150));
+        assert_eq!(result.average_price.round_dp(4), Decimal::new(1428571, 4));
+        assert!(!result.exhausted);
+
+        let result = market_impact(&OrderBook::asks(), Decimal::from(5000), last_price).unwrap();
+        assert_eq!(result.filled, Decimal::from(2250));
+        assert_eq!(result.worst_price, Decimal::from(250));
+        assert!(result.exhausted);
+
+        let result = market_impact(&OrderBook::bids(), Decimal::from(450), last_price).unwrap();
+        assert_eq!(result.average_price, Decimal::from(90));
+        assert_eq!(result.slippage.round_dp(4), Decimal::new(90909, 4));
+
+        assert_eq!(market_impact(&[], Decimal::from(1000), last_price), None);
+
+        // the notional underflows to zero base qty
+        let dust = Decimal::from_parts(1, 0, 0, false, 28);
+        assert_eq!(market_impact(&OrderBook::asks(), dust, last_price), None);
+    }
+
+    #[test]
+    fn test_depth_bands() {
+        let book = trimmed(OrderBook::default());
+
+        let result = depth_bands(&book, Decimal::from(95), Decimal::from(8));
+        let bounds = result.iter().map(|band| (band.from, band.to)).collect::<Vec<_>>();
+        let expected = [(0, 1), (1, 2), (2, 5), (5, 8)].map(|(from, to)| (Decimal::from(from), Decimal::from(to)));
+        assert_eq!(bounds, expected.to_vec());
+
+        // the 100 ask and the 90 bid are both 5.26% away
+        assert_eq!(result[3].bids, Decimal::from(900));
+        assert_eq!(result[3].asks, Decimal::ONE_HUNDRED);
+        assert_eq!(result[3].imbalance(), Decimal::new(8, 1));
+        assert_eq!(result[3].label(), "bullish");
+        assert_eq!(result[0].label(), "neutral");
+
+        let result = depth_bands(&book, Decimal::from(95), Decimal::ONE);
+        assert_eq!(result.len(), 1);
+    }
+
+    #[test]
+    fn test_score_walls() {
+        // notionals 900, 8500, 160, 75 with the median of 530
+        let result = score_walls(levels(OrderBook::bids()), Decimal::ONE_HUNDRED, Decimal::from(25));
+
+        let score = result[1].score;
+        assert_eq!(score.size_vs_median.round_dp(4), Decimal::new(160377, 4));
+        assert_eq!(score.side_share.round_dp(4), Decimal::new(882200, 4));
+        assert_eq!(score.distance, Decimal::from(15));
+        assert_eq!(score.score.round_dp(4), Decimal::new(211304, 4));
+
+        let before = result.iter().map(|wall| wall.notional_before).collect::<Vec<_>>();
+        assert_eq!(before, [0, 900, 9400, 9560].map(Decimal::from).to_vec());
+
+        let result = sort_and_filter(result, WallSort::Score);
+        assert_eq!(result[0].level.price, Decimal::from(85));
+        assert_eq!(result[1].level.price, Decimal::from(90));
+    }
+
+    #[test]
+    fn test_find_liquidity_gaps() {
+        // 10% slices with 100, 1500 and 400 USD at 0%, 50% and 100% from price
+        let book = OrderBook::default();
+        let covered = find_covered_range(&book.asks, OrderType::Ask);
+        let result = find_liquidity_gaps(&trimmed(book), covered, Decimal::ONE_HUNDRED, Decimal::ONE_HUNDRED, OrderType::Ask);
+        let ranges = result.iter().map(|gap| gap.range).collect::<Vec<_>>();
+        let expected = [(110, 150), (160, 190)].map(|(nearest, farthest)| PriceRange {
+            nearest: Decimal::from(nearest),
+            farthest: Decimal::from(farthest),
+        });
+        assert_eq!(ranges, expected.to_vec());
+        assert!(result.iter().all(|gap| gap.notional.is_zero()));
+
+        // bids start 10% away from price, nothing is known beyond the 75 bid
+        let book = OrderBook::default();
+        let covered = find_covered_range(&book.bids, OrderType::Bid);
+        let result = find_liquidity_gaps(&trimmed(book), covered, Decimal::ONE_HUNDRED, Decimal::from(50), OrderType::Bid);
+        let ranges = result.iter().map(|gap| gap.range).collect::<Vec<_>>();
+        let expected = [(100, 90), (80, 75)].map(|(nearest, farthest)| PriceRange {
+            nearest: Decimal::from(nearest),
+            farthest: Decimal::from(farthest),
+        });
+        assert_eq!(ranges, expected.to_vec());
+
+        let empty = trimmed(OrderBook { asks: vec![], bids: vec![] });
+        let result = find_liquidity_gaps(&empty, None, Decimal::ONE_HUNDRED, Decimal::TEN, OrderType::Bid);
+        assert!(result.is_empty());
+
+        let book = OrderBook::default();
+        let covered = find_covered_range(&book.asks, OrderType::Ask);
+        let result = find_liquidity_gaps(&trimmed(book), covered, Decimal::ONE_HUNDRED, Decimal::ZERO, OrderType::Ask);
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_sort_and_filter() {
+        let walls = score_walls(levels(OrderBook::bids()), Decimal::ONE_HUNDRED, Decimal::from(25));
+
+        let result = sort_and_filter(walls, WallSort::Notional)
+            .into_iter()
+            .map(|wall| wall.level)
+            .collect::<Vec<_>>();
+        let expected = levels(vec![
+            OrderBookEntity::new(Decimal::from(85), Decimal::ONE_HUNDRED),
+            OrderBookEntity::new(Decimal::from(90), Decimal::TEN),
+            OrderBookEntity::new(Decimal::from(80), Decimal::TWO),
+            OrderBookEntity::new(Decimal::from(75), Decimal::ONE),
+        ]);
 
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_merge_order_books() {
+        let binance = OrderBook::default().with_exchange(ExchangeId::Binance);
+        let bybit = OrderBook {
+            asks: vec![OrderBookEntity::new(Decimal::from(120), Decimal::ONE)],
+            bids: vec![OrderBookEntity::new(Decimal::from(85), Decimal::TWO)],
+        }
+        .with_exchange(ExchangeId::Bybit);
+
+        let result = merge_order_books(vec![binance, bybit]);
+
+        let ask_prices = result.asks.iter().map(|entity| entity.price).collect::<Vec<_>>();
+        let expected = [100, 120, 150, 200, 250].map(Decimal::from).to_vec();
+        assert_eq!(ask_prices, expected);
+        assert_eq!(result.asks[1].exchange, Some(ExchangeId::Bybit));
+
+        // 85 is quoted on both exchanges and becomes one level
+        let bid_prices = result.bids.iter().map(|entity| entity.price).collect::<Vec<_>>();
+        let expected = [90, 85, 80, 75].map(Decimal::from).to_vec();
+        assert_eq!(bid_prices, expected);
+        assert_eq!(result.bids[1].qty, Decimal::from(102));
+        assert_eq!(result.bids[1].exchange, None);
+        assert_eq!(result.bids[0].exchange, Some(ExchangeId::Binance));
+    }
 }
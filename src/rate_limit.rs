@@ -0,0 +1,125 @@
+use std::sync::Mutex;
+
+use crate::error::{Result, ServiceError};
+use crate::time::unix_now;
+
+const WINDOW_SECS: u64 = 60;
+
+#[derive(Debug, Default)]
+struct WeightState {
+    window: u64,
+    used: u32,
+    blocked_until: u64,
+}
+
+/// Tracks request weight spent within the current minute, the way Binance counts it,
+/// and rejects requests that would exceed the limit or arrive during a ban.
+#[derive(Debug)]
+pub struct WeightLimiter {
+    limit: u32,
+    state: Mutex<WeightState>,
+}
+
+impl WeightLimiter {
+    pub fn new(limit: u32) -> Self {
+        WeightLimiter {
+            limit,
+            state: Mutex::new(WeightState::default()),
+        }
+    }
+
+    /// Reserves the weight of a request or returns `RateLimited` with seconds to wait.
+    pub fn acquire(&self, weight: u32) -> Result<()> {
+        self.acquire_at(weight, unix_now())
+    }
+
+    /// Syncs the used weight with the `X-MBX-USED-WEIGHT-1M` header of a response.
+    pub fn update_used(&self, used: u32) {
+        self.update_used_at(used, unix_now())
+    }
+
+    /// Blocks all requests for `secs` seconds after a 429 or 418 response.
+    pub fn block_for(&self, secs: u64) {
+        self.block_for_at(secs, unix_now())
+    }
+
+    fn acquire_at(&self, weight: u32, now: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.blocked_until > now {
+            return Err(ServiceError::RateLimited(state.blocked_until - now));
+        }
+
+        let window = now / WINDOW_SECS;
+        if state.window != window {
+            state.window = window;
+            state.used = 0;
+        }
+
+        if state.used + weight > self.limit {
+            return Err(ServiceError::RateLimited(WINDOW_SECS - now % WINDOW_SECS));
+        }
+
+        state.used += weight;
+        Ok(())
+    }
+
+    fn update_used_at(&self, used: u32, now: u64) {
+        let mut state = self.state.lock().unwrap();
+
+        state.window = now / WINDOW_SECS;
+        state.used = used;
+    }
+
+    fn block_for_at(&self, secs: u64, now: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.blocked_until = state.blocked_until.max(now + secs);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_acquire_within_limit() {
+        let limiter = WeightLimiter::new(500);
+        let now = 120;
+
+        assert!(limiter.acquire_at(250, now).is_ok());
+        assert!(limiter.acquire_at(250, now + 10).is_ok());
+
+        match limiter.acquire_at(2, now + 15) {
+            Err(ServiceError::RateLimited(secs)) => assert_eq!(secs, 45),
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        // the next minute starts with a fresh budget
+        assert!(limiter.acquire_at(250, now + 60).is_ok());
+    }
+
+    #[test]
+    fn test_update_used_from_header() {
+        let limiter = WeightLimiter::new(500);
+        let now = 120;
+
+        limiter.update_used_at(400, now);
+        assert!(limiter.acquire_at(250, now + 1).is_err());
+        assert!(limiter.acquire_at(100, now + 1).is_ok());
+    }
+
+    #[test]
+    fn test_block_for() {
+        let limiter = WeightLimiter::new(500);
+        let now = 120;
+
+        limiter.block_for_at(30, now);
+
+        match limiter.acquire_at(1, now + 10) {
+            Err(ServiceError::RateLimited(secs)) => assert_eq!(secs, 20),
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        assert!(limiter.acquire_at(1, now + 30).is_ok());
+    }
+}
@@ -2,6 +2,7 @@ use log::error;
 use redis::{AsyncCommands, Client};
 
 use crate::error::{Result, ServiceError};
+use crate::exchange::ExchangeId;
 use crate::order_book::OrderBook;
 
 const ORDER_BOOK_TTL: u64 = 60;
@@ -17,12 +18,12 @@ impl Redis {
         Ok(Redis { client })
     }
 
-    fn build_redis_key(&self, symbol: &str) -> String {
-        format!("orderbook-{}", symbol)
+    fn build_redis_key(&self, exchange: ExchangeId, symbol: &str) -> String {
+        format!("orderbook-{}-{}", exchange, symbol)
     }
 
-    pub async fn get_order_book(&self, symbol: &str) -> Result<Option<OrderBook>> {
-        let key = self.build_redis_key(symbol);
+    pub async fn get_order_book(&self, exchange: ExchangeId, symbol: &str) -> Result<Option<OrderBook>> {
+        let key = self.build_redis_key(exchange, symbol);
         let mut conn = self.client.get_multiplexed_async_connection().await?;
 
         let result: Option<String> = conn.get(key).await?;
@@ -33,13 +34,13 @@ impl Redis {
                     error!("Failed to deserialize redis data: {}", e);
                     ServiceError::from(e)
                 })
-                .map(|book| Some(book)),
+                .map(Some),
             None => Ok(None),
         }
     }
 
-    pub async fn add_order_book(&self, symbol: &str, book: &OrderBook) -> Result<()> {
-        let key = self.build_redis_key(symbol);
+    pub async fn add_order_book(&self, exchange: ExchangeId, symbol: &str, book: &OrderBook) -> Result<()> {
+        let key = self.build_redis_key(exchange, symbol);
         let mut conn = self.client.get_multiplexed_async_connection().await?;
 
         let book = serde_json::to_string(book).unwrap();
@@ -60,13 +61,13 @@ mod test {
         let redis = Redis::new("redis://localhost:6379".to_string()).unwrap();
 
         let order_book = OrderBook::default();
-        let result = redis.add_order_book("SOLUSDT", &order_book).await;
+        let result = redis.add_order_book(ExchangeId::Binance, "SOLUSDT", &order_book).await;
         assert!(result.is_ok());
 
-        let result = redis.get_order_book("SOLUSDT").await.unwrap().unwrap();
+        let result = redis.get_order_book(ExchangeId::Binance, "SOLUSDT").await.unwrap().unwrap();
         assert_eq!(result, order_book);
 
-        let result = redis.get_order_book("BTCETH").await.unwrap();
+        let result = redis.get_order_book(ExchangeId::Binance, "BTCETH").await.unwrap();
         assert!(result.is_none());
     }
 }
@@ -1,7 +1,8 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::time::Duration;
 
+use futures::future::join_all;
 use log::{error, info};
 use rust_decimal::Decimal;
 use teloxide::types::ChatId;
@@ -9,81 +10,350 @@ use tokio::sync::RwLock;
 use tokio::time::interval;
 
 use crate::binance::Binance;
+use crate::bybit::Bybit;
+use crate::config::{BinanceConfig, WallTrackingConfig};
 use crate::error::{Result, ServiceError};
-use crate::order_book::{ExtendedOrderBook, OrderBook, OrderType, process_order_book_entity};
+use crate::exchange::{Exchange, ExchangeId, Market, OrderBookSource, Precision};
+use crate::local_book::{Icebergs, LocalOrderBooks};
+use crate::okx::Okx;
+use crate::order_book::{
+    ExtendedOrderBook, Grouping, ImpactEstimate, OrderBook, OrderType, WallSort, cumulative_depth, depth_bands,
+    find_covered_range, find_liquidity_gaps, market_impact, merge_order_books, process_order_book_entity,
+    trim_order_book,
+};
 use crate::redis::Redis;
-
-const DEPTH_EXEPCTIONS: [&str; 4] = ["BTCUSDT", "ETHUSDT", "WBTCUSDT", "WETHUSDT"];
+use crate::time::unix_now;
+use crate::wall_tracker::{Snapshot, SymbolWalls, WallTracker};
 
 pub struct AppState {
-    binance: Binance,
-    trading_pairs: RwLock<HashSet<String>>,
+    exchanges: Vec<Arc<dyn Exchange>>,
+    /// USDT pairs listed by each exchange along with their precision
+    trading_pairs: RwLock<HashMap<ExchangeId, HashMap<String, Option<Precision>>>>,
     redis: Redis,
+    local_books: Arc<LocalOrderBooks>,
+    wall_tracker: WallTracker,
     allowed_users: HashSet<ChatId>,
 }
 
 impl AppState {
-    pub fn new(redis_config: String, allowed_users: HashSet<ChatId>) -> Self {
+    pub fn new(
+        redis_config: String,
+        allowed_users: HashSet<ChatId>,
+        binance: &BinanceConfig,
+        binance_spot: Arc<Binance>,
+        local_books: Arc<LocalOrderBooks>,
+    ) -> Self {
         let redis = Redis::new(redis_config).expect("Failed to connect to Redis");
 
         AppState {
-            binance: Binance::new(),
-            trading_pairs: RwLock::new(HashSet::new()),
+            exchanges: vec![
+                binance_spot,
+                Arc::new(Bybit::new()),
+                Arc::new(Okx::new()),
+                Arc::new(Binance::futures(binance)),
+            ],
+            trading_pairs: RwLock::new(HashMap::new()),
             redis,
+            local_books,
+            wall_tracker: WallTracker::new(),
             allowed_users,
         }
     }
 
-    async fn get_usdt_trading_pairs(&self) -> Result<Vec<String>> {
-        let exch_info = self
-            .binance
+    fn exchange(&self, id: ExchangeId) -> Result<&dyn Exchange> {
+        self.exchanges
+            .iter()
+            .find(|exchange| exchange.id() == id)
+            .map(|exchange| exchange.as_ref())
+            .ok_or_else(|| ServiceError::internal(format!("{} is not configured", id)))
+    }
+
+    async fn get_usdt_trading_pairs(&self, exchange: &dyn Exchange) -> Result<Vec<(String, Option<Precision>)>> {
+        let exch_info = exchange
             .get_exchange_info()
             .await?
             .into_iter()
-            .filter(|item| item.status == "TRADING" && item.symbol.ends_with("USDT"))
-            .map(|item| item.symbol)
+            .filter(|item| item.is_trading && item.symbol.ends_with("USDT"))
+            .map(|item| (item.symbol, item.precision))
             .collect();
 
         Ok(exch_info)
     }
 
-    async fn get_order_book(&self, symbol: &str) -> Result<OrderBook> {
-        let redis_ob = self.redis.get_order_book(symbol).await?;
+    /// Order book from the cache if it is there. `fresh` always requests the exchange
+    /// and only refreshes the cache.
+    async fn get_order_book(&self, exchange: &dyn Exchange, symbol: &str, fresh: bool) -> Result<OrderBook> {
+        let redis_ob = if fresh { None } else { self.redis.get_order_book(exchange.id(), symbol).await? };
 
         match redis_ob {
             Some(ob) => Ok(ob),
-            None => match self.binance.get_order_book(&symbol).await {
-                Ok(book) => {
-                    let _ = self.redis.add_order_book(symbol, &book).await.map_err(|e| {
-                        error!("Failed to save order book for {} due to error: {}", symbol, e);
-                    });
-                    Ok(book)
-                },
-                Err(e) => Err(ServiceError::from(e))
+            None => {
+                let book = exchange.get_order_book(symbol).await?;
+                let _ = self.redis.add_order_book(exchange.id(), symbol, &book).await.map_err(|e| {
+                    error!("Failed to save {} order book for {} due to error: {}", exchange.id(), symbol, e);
+                });
+                Ok(book)
             }
         }
     }
 
+    async fn get_exchange_order_book(
+        &self,
+        exchange: &dyn Exchange,
+        symbol: &str,
+        fresh: bool,
+    ) -> Result<(Decimal, OrderBook)> {
+        // hot symbols are answered from memory without spending REST weight
+        if exchange.id() == ExchangeId::Binance
+            && let Some(local) = self.local_books.get(symbol).await
+        {
+            return Ok(local);
+        }
+
+        let last_price = exchange.get_last_price(symbol).await?;
+        let order_book = self.get_order_book(exchange, symbol, fresh).await?;
+
+        Ok((last_price, order_book))
+    }
+
+    /// Fetches the symbol from every spot exchange listing it and merges the books.
+    /// Exchanges that fail are skipped, the last price is averaged over the rest.
+    async fn get_aggregated_order_book(&self, symbol: &str) -> Result<(Decimal, OrderBook)> {
+        let listed = self.get_listing_exchanges(symbol, Market::Spot).await?;
+
+        let requests = self
+            .exchanges
+            .iter()
+            .filter(|exchange| listed.contains(&exchange.id()))
+            .map(|exchange| async move {
+                self.get_exchange_order_book(exchange.as_ref(), symbol, false)
+                    .await
+                    .map(|(last_price, book)| (last_price, book.with_exchange(exchange.id())))
+                    .map_err(|e| {
+                        error!("Failed to get {} order book for {}: {}", exchange.id(), symbol, e);
+                        e
+                    })
+            });
+
+        let (prices, books): (Vec<_>, Vec<_>) = join_all(requests)
+            .await
+            .into_iter()
+            .filter_map(|result| result.ok())
+            .unzip();
+
+        if books.is_empty() {
+            return Err(ServiceError::internal(format!("No order books received for {}", symbol)));
+        }
+
+        let last_price = prices.iter().sum::<Decimal>() / Decimal::from(prices.len());
+
+        Ok((last_price, merge_order_books(books)))
+    }
+
+    /// Precision of the symbol on the source, the finest one across exchanges
+    /// for an aggregated book. `None` if no exchange reported it.
+    async fn get_precision(&self, symbol: &str, source: OrderBookSource) -> Option<Precision> {
+        let exchanges = match source {
+            OrderBookSource::Exchange(exchange) => vec![exchange],
+            OrderBookSource::Aggregated => self.get_listing_exchanges(symbol, Market::Spot).await.ok()?,
+        };
+
+        let exch_info = self.trading_pairs.read().await;
+
+        exchanges
+            .iter()
+            .filter_map(|id| exch_info.get(id)?.get(symbol).copied().flatten())
+            .reduce(Precision::finest)
+    }
+
     pub async fn get_filtered_order_book(
         &self,
         symbol: String,
+        source: OrderBookSource,
         depth: Decimal,
+        grouping: Grouping,
+        sort: WallSort,
     ) -> Result<ExtendedOrderBook> {
-        let last_price = self.binance.get_last_price(&symbol).await?;
-        let order_book = self.get_order_book(&symbol).await?;
+        let fetched = self.get_source_order_book(&symbol, source).await?;
+        let mut book = self.extend_order_book(symbol, source, fetched, depth, grouping, sort).await;
+
+        self.wall_tracker.mark_suspected(&mut book).await;
+        Ok(book)
+    }
 
-        let asks = process_order_book_entity(order_book.asks, last_price.price, depth, OrderType::Ask);
-        let bids = process_order_book_entity(order_book.bids, last_price.price, depth, OrderType::Bid);
+    async fn get_source_order_book(&self, symbol: &str, source: OrderBookSource) -> Result<(Decimal, OrderBook)> {
+        match source {
+            OrderBookSource::Exchange(exchange) => {
+                self.get_exchange_order_book(self.exchange(exchange)?, symbol, false).await
+            }
+            OrderBookSource::Aggregated => self.get_aggregated_order_book(symbol).await,
+        }
+    }
+
+    async fn extend_order_book(
+        &self,
+        symbol: String,
+        source: OrderBookSource,
+        (last_price, order_book): (Decimal, OrderBook),
+        depth: Decimal,
+        grouping: Grouping,
+        sort: WallSort,
+    ) -> ExtendedOrderBook {
+        let asks_range = find_covered_range(&order_book.asks, OrderType::Ask);
+        let bids_range = find_covered_range(&order_book.bids, OrderType::Bid);
 
-        Ok(ExtendedOrderBook {
+        let precision = self.get_precision(&symbol, source).await;
+        let bucket_size = grouping.bucket_size(last_price, precision);
+
+        // every metric below reads the same rounded levels
+        let book = trim_order_book(order_book, last_price, depth, precision);
+        let cumulative_depth = cumulative_depth(&book, last_price);
+        let depth_bands = depth_bands(&book, last_price, depth);
+        let asks_gaps = find_liquidity_gaps(&book, asks_range, last_price, depth, OrderType::Ask);
+        let bids_gaps = find_liquidity_gaps(&book, bids_range, last_price, depth, OrderType::Bid);
+
+        let process = |order_type| process_order_book_entity(&book, last_price, depth, order_type, bucket_size, sort);
+
+        let asks = process(OrderType::Ask);
+        let bids = process(OrderType::Bid);
+
+        ExtendedOrderBook {
             symbol,
+            source,
             asks,
             bids,
-            last_price: last_price.price,
+            last_price,
             depth,
+            asks_range,
+            bids_range,
+            precision,
+            grouping,
+            bucket_size,
+            sort,
+            cumulative_depth,
+            depth_bands,
+            asks_gaps,
+            bids_gaps,
+        }
+    }
+
+    /// Top walls of the spot and perpetual books of the same symbol, spot taken
+    /// from the first configured exchange listing it.
+    pub async fn get_spot_futures_comparison(
+        &self,
+        symbol: String,
+        depth: Decimal,
+    ) -> Result<(ExtendedOrderBook, ExtendedOrderBook)> {
+        let spot = self.get_listing_exchanges(&symbol, Market::Spot).await?[0];
+        let futures = self.get_listing_exchanges(&symbol, Market::Futures).await?[0];
+
+        tokio::try_join!(
+            self.get_filtered_order_book(
+                symbol.clone(),
+                OrderBookSource::Exchange(spot),
+                depth,
+                Grouping::Levels,
+                WallSort::Notional,
+            ),
+            self.get_filtered_order_book(
+                symbol,
+                OrderBookSource::Exchange(futures),
+                depth,
+                Grouping::Levels,
+                WallSort::Notional,
+            ),
+        )
+    }
+
+    /// Walks the book of the first spot exchange listing the symbol with a
+    /// market buy and sell of `size` USD.
+    pub async fn get_market_impact(&self, symbol: String, size: Decimal) -> Result<ImpactEstimate> {
+        let exchange = self.get_listing_exchanges(&symbol, Market::Spot).await?[0];
+        let (last_price, order_book) = self.get_exchange_order_book(self.exchange(exchange)?, &symbol, false).await?;
+        let precision = self.get_precision(&symbol, OrderBookSource::Exchange(exchange)).await;
+
+        Ok(ImpactEstimate {
+            buy: market_impact(&order_book.asks, size, last_price),
+            sell: market_impact(&order_book.bids, size, last_price),
+            symbol,
+            exchange,
+            size,
+            last_price,
+            precision,
         })
     }
 
+    /// Takes the top walls of the first spot exchange listing the symbol and
+    /// matches them against the ones seen before. Trades since the previous
+    /// snapshot are only known for Binance hot symbols.
+    async fn snapshot_walls(&self, symbol: &str, depth: Decimal) -> Result<()> {
+        let exchange = self.get_listing_exchanges(symbol, Market::Spot).await?[0];
+        let source = OrderBookSource::Exchange(exchange);
+        // the cache would return the same book to snapshots taken within its TTL
+        let (last_price, order_book) = self.get_exchange_order_book(self.exchange(exchange)?, symbol, true).await?;
+
+        let trades = match (exchange, self.wall_tracker.updated_at(symbol).await) {
+            (ExchangeId::Binance, Some(since)) => self.local_books.get_trades_since(symbol, since).await,
+            _ => None,
+        };
+
+        let book = self
+            .extend_order_book(
+                symbol.to_string(),
+                source,
+                (last_price, order_book.clone()),
+                depth,
+                Grouping::Levels,
+                WallSort::Notional,
+            )
+            .await;
+
+        let snapshot = Snapshot {
+            book: &order_book,
+            last_price,
+            trades: trades.as_deref(),
+            precision: book.precision,
+        };
+
+        self.wall_tracker.update(exchange, &book, &snapshot, unix_now()).await;
+        Ok(())
+    }
+
+    /// Icebergs of a Binance hot symbol, `None` for the rest
+    pub async fn get_icebergs(&self, symbol: &str) -> Option<Icebergs> {
+        self.local_books.get_icebergs(symbol).await
+    }
+
+    /// Walls of a watched symbol, `None` if it isn't watched or wasn't snapshotted yet
+    pub async fn get_tracked_walls(&self, symbol: &str) -> Option<SymbolWalls> {
+        self.wall_tracker.get(symbol).await
+    }
+
+    async fn listed_exchanges(&self, symbol: &str) -> Vec<ExchangeId> {
+        let exch_info = self.trading_pairs.read().await;
+
+        self.exchanges
+            .iter()
+            .map(|exchange| exchange.id())
+            .filter(|id| exch_info.get(id).is_some_and(|pairs| pairs.contains_key(symbol)))
+            .collect()
+    }
+
+    pub async fn get_listing_exchanges(&self, symbol: &str, market: Market) -> Result<Vec<ExchangeId>> {
+        let (listed, listed_elsewhere): (Vec<_>, Vec<_>) = self
+            .listed_exchanges(symbol)
+            .await
+            .into_iter()
+            .partition(|exchange| exchange.market() == market);
+
+        if listed.is_empty() {
+            let other_market = (!listed_elsewhere.is_empty()).then(|| market.other());
+            return Err(ServiceError::SymbolNotFound(symbol.to_string(), other_market));
+        }
+
+        Ok(listed)
+    }
+
     pub async fn validate_symbol(&self, symbol: &str) -> Result<String> {
         let symbol = symbol.to_uppercase();
 
@@ -93,16 +363,11 @@ impl AppState {
             symbol
         };
 
-        if DEPTH_EXEPCTIONS.contains(&symbol.as_str()) {
-            return Err(ServiceError::UnsupportedSymbol(symbol));
+        if self.listed_exchanges(&symbol).await.is_empty() {
+            return Err(ServiceError::SymbolNotFound(symbol, None));
         }
 
-        let exch_info = self.trading_pairs.read().await;
-
-        match exch_info.get(&symbol) {
-            None => Err(ServiceError::SymbolNotFound(symbol)),
-            Some(item) => Ok(item.to_owned()),
-        }
+        Ok(symbol)
     }
 
     pub async fn authorize(&self, chat_id: ChatId) -> Result<()> {
@@ -116,16 +381,35 @@ impl AppState {
 
 pub async fn periodic_exchange_info_update(state: Arc<AppState>) {
     let mut interval = interval(Duration::from_secs(300));
-    info!("Updating exchange info Binance Spot");
 
     loop {
         interval.tick().await;
 
-        match state.get_usdt_trading_pairs().await {
-            Err(e) => error!("Failed to update exchange info Binance Futures: {}", e),
-            Ok(data) => {
-                let mut lock = state.trading_pairs.write().await;
-                lock.extend(data);
+        for exchange in state.exchanges.iter() {
+            info!("Updating exchange info {}", exchange.id());
+
+            match state.get_usdt_trading_pairs(exchange.as_ref()).await {
+                Err(e) => error!("Failed to update exchange info {}: {}", exchange.id(), e),
+                Ok(data) => {
+                    let mut lock = state.trading_pairs.write().await;
+                    lock.entry(exchange.id()).or_default().extend(data);
+                }
+            }
+        }
+    }
+}
+
+pub async fn track_walls(state: Arc<AppState>, config: WallTrackingConfig) {
+    let mut interval = interval(Duration::from_secs(config.interval_secs.max(1)));
+
+    loop {
+        interval.tick().await;
+
+        for symbol in config.symbols.iter() {
+            let symbol = symbol.to_uppercase();
+
+            if let Err(e) = state.snapshot_walls(&symbol, config.depth).await {
+                error!("Failed to snapshot walls of {}: {}", symbol, e);
             }
         }
     }
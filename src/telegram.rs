@@ -2,12 +2,21 @@ use numfmt::Formatter;
 use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
 
-use crate::order_book::{OrderBookEntity, ExtendedOrderBook};
+use crate::error::ServiceError;
+use crate::exchange::Precision;
+use crate::local_book::Icebergs;
+use crate::order_book::{
+    DepthBand, ExtendedOrderBook, Grouping, ImpactEstimate, LiquidityGap, MarketImpact, OrderType, PriceRange,
+    Wall, WallScore, WallSort, round_price,
+};
+use crate::wall_tracker::{GoneReason, SymbolWalls, TrackedWall};
 
 const MARKDOVWN2_ESCAPE_SYMBOLS: &str = r#"\\[]()~>#\+-={}.!""#;
 const MARKDOVWN2_SYMBOLS: &str = r#"*_"#;
+/// Marks walls that were pulled around the same price before
+const SPOOF_MARKER: &str = "⚠️ spoof?";
 
-fn escape_markdown_v2(text: String) -> String {
+pub fn escape_markdown_v2(text: String) -> String {
     text.chars().fold(String::with_capacity(text.len()), |mut acc, char| {
         if MARKDOVWN2_ESCAPE_SYMBOLS.contains(char) && !MARKDOVWN2_SYMBOLS.contains(char) {
             acc.push('\\');
@@ -17,37 +26,459 @@ fn escape_markdown_v2(text: String) -> String {
     })
 }
 
+/// Reply to a failed order book request, chosen by the kind of error
+pub fn format_error(e: &ServiceError) -> String {
+    let msg = match e {
+        ServiceError::SymbolNotFound(..) => format!("{} ❌", e),
+        ServiceError::Unauthorized | ServiceError::RateLimited(_) => e.to_string(),
+        ServiceError::Unavailable(_) => "Exchange is unavailable right now. Try again in a minute".to_string(),
+        ServiceError::BadRequest(_) => "Exchange rejected the request. Check the token and try again ❌".to_string(),
+        ServiceError::Internal(_) => "Something went wrong. Try again later".to_string(),
+    };
+
+    escape_markdown_v2(msg)
+}
+
 fn format_num(f: &mut Formatter, num: Decimal) -> String {
     let num = num.to_f64().unwrap();
     let num = f.fmt2(num);
     num.to_owned()
 }
 
-fn format_order_book(mut f: &mut Formatter, book: Vec<OrderBookEntity>) -> String {
-    let mut book = book.into_iter().enumerate().map(|(index, entity)| match index {
-            0 => format!("{}  •  {} 🥇", entity.price, format_num(&mut f, entity.qty)),
-            1 => format!("{}  •  {} 🥈", entity.price, format_num(&mut f, entity.qty)),
-            2 => format!("{}  •  {} 🥉", entity.price, format_num(&mut f, entity.qty)),
-            _ => format!("{}  •  {}", entity.price, format_num(&mut f, entity.qty)),
+/// Score along with the size vs median and the share of the side it is made of
+fn format_score(score: &WallScore) -> String {
+    format!(
+        "⭐{} (×{}, {}%)",
+        score.score.round_dp(1).normalize(),
+        score.size_vs_median.round_dp(1).normalize(),
+        score.side_share.round_dp(0).normalize()
+    )
+}
+
+fn format_wall(f: &mut Formatter, wall: &Wall, bucket_size: Option<Decimal>, base_asset: &str) -> String {
+    let level = &wall.level;
+    let price = match bucket_size {
+        Some(size) => format!("{}–{}", level.price, (level.price + size).normalize()),
+        None => level.price.to_string(),
+    };
+    let size = format!(
+        "${} ({} {})",
+        format_num(f, level.notional),
+        format_num(f, level.base_qty),
+        base_asset
+    );
+    let score = format_score(&wall.score);
+    // how far the wall is and how much has to be eaten before reaching it
+    let distance = format!(
+        "{}% away, ${} before",
+        wall.score.distance.round_dp(2).normalize(),
+        format_num(f, wall.notional_before)
+    );
+
+    let row = match level.exchange {
+        Some(exchange) => format!("{}  •  {}  •  {}  •  {}  •  {}", price, size, distance, score, exchange),
+        None => format!("{}  •  {}  •  {}  •  {}", price, size, distance, score),
+    };
+
+    // walls were pulled around this price before
+    if wall.suspected_spoof { format!("{}  •  {}", row, SPOOF_MARKER) } else { row }
+}
+
+fn format_order_book(f: &mut Formatter, book: Vec<Wall>, bucket_size: Option<Decimal>, base_asset: &str) -> String {
+    let mut book = book.into_iter().enumerate().map(|(index, wall)| match index {
+            0 => format!("{} 🥇", format_wall(f, &wall, bucket_size, base_asset)),
+            1 => format!("{} 🥈", format_wall(f, &wall, bucket_size, base_asset)),
+            2 => format!("{} 🥉", format_wall(f, &wall, bucket_size, base_asset)),
+            _ => format_wall(f, &wall, bucket_size, base_asset),
         })
         .collect::<Vec<_>>();
 
-    book.sort_by(|book1, book2| book2.cmp(&book1));
+    book.sort_by(|book1, book2| book2.cmp(book1));
     book.join("\n")
 }
 
+fn format_comparison_rows(
+    f: &mut Formatter,
+    spot: &[Wall],
+    futures: &[Wall],
+) -> String {
+    let format_cell = |f: &mut Formatter, wall: Option<&Wall>| match wall {
+        Some(Wall { level, .. }) => format!("{:<10} {:>6}", level.price, format_num(f, level.notional)),
+        None => format!("{:<10} {:>6}", "-", ""),
+    };
+
+    (0..spot.len().max(futures.len()))
+        .map(|index| {
+            let spot = format_cell(f, spot.get(index));
+            let futures = format_cell(f, futures.get(index));
+            format!("{} | {}", spot, futures)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Spot and futures walls side by side, ranked by volume. The table is sent
+/// as a code block so the columns stay aligned.
+pub fn format_comparison(spot: ExtendedOrderBook, futures: ExtendedOrderBook) -> String {
+    let mut f = Formatter::default();
+
+    let header = format!(
+        "*{}*\n\nTop 10 limits of {}% depth\nLeft: {} spot, last price {}\nRight: {}, last price {}",
+        spot.symbol,
+        spot.depth,
+        spot.source,
+        spot.rounded_last_price(),
+        futures.source,
+        futures.rounded_last_price(),
+    );
+
+    let volumes = |f: &mut Formatter, name: &str, spot: Decimal, futures: Decimal| {
+        format!("{:<10} {:>6} | {:<10} {:>6}", name, format_num(f, spot), name, format_num(f, futures))
+    };
+
+    let asks_vol = volumes(&mut f, "Asks vol", spot.asks_volume(), futures.asks_volume());
+    let bids_vol = volumes(&mut f, "Bids vol", spot.bids_volume(), futures.bids_volume());
+    let asks = format_comparison_rows(&mut f, &spot.asks, &futures.asks);
+    let bids = format_comparison_rows(&mut f, &spot.bids, &futures.bids);
+
+    let coverage = [&spot, &futures]
+        .into_iter()
+        .flat_map(|book| {
+            coverage_warnings(book)
+                .into_iter()
+                .map(|warning| format!("\n{}: {}", book.source, warning))
+        })
+        .collect::<String>();
+
+    format!(
+        "{}\n```\nASKS\n{}\n\nBIDS\n{}\n\n{}\n{}\n```{}",
+        escape_markdown_v2(header), asks, bids, asks_vol, bids_vol, escape_markdown_v2(coverage)
+    )
+}
+
+fn format_side_coverage(side: &str, range: Option<PriceRange>, covered: Decimal, depth: Decimal) -> Option<String> {
+    if covered >= depth {
+        return None;
+    }
+
+    let msg = match range {
+        Some(range) => format!(
+            "⚠️ {} reach only {}% (up to {}) of {}% depth",
+            side,
+            covered.round_dp(2).normalize(),
+            range.farthest.normalize(),
+            depth
+        ),
+        None => format!("⚠️ No {} received", side.to_lowercase()),
+    };
+
+    Some(msg)
+}
+
+fn coverage_warnings(book: &ExtendedOrderBook) -> Vec<String> {
+    [
+        format_side_coverage("Asks", book.asks_range, book.asks_covered_depth(), book.depth),
+        format_side_coverage("Bids", book.bids_range, book.bids_covered_depth(), book.depth),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Cumulative liquidity per side, marked with ≥ where the fetched book ends closer to price
+fn format_cumulative_depth(f: &mut Formatter, book: &ExtendedOrderBook) -> String {
+    let format_side = |f: &mut Formatter, notional: Decimal, covered: Decimal, percent: Decimal| {
+        let sign = if covered < percent { "≥" } else { "" };
+        format!("{}${}", sign, format_num(f, notional))
+    };
+
+    book.cumulative_depth
+        .iter()
+        .map(|depth| {
+            let asks = format_side(f, depth.asks, book.asks_covered_depth(), depth.percent);
+            let bids = format_side(f, depth.bids, book.bids_covered_depth(), depth.percent);
+            format!("\n±{}%  asks {}  •  bids {}", depth.percent, asks, bids)
+        })
+        .collect()
+}
+
+fn format_imbalance(band: &DepthBand) -> String {
+    let percent = (band.imbalance() * Decimal::ONE_HUNDRED).round_dp(0).normalize();
+    let sign = if percent > Decimal::ZERO { "+" } else { "" };
+    format!("{}{}% {}", sign, percent, band.label())
+}
+
+/// Bids against asks notional over the whole depth and in each band
+fn format_depth_bands(book: &ExtendedOrderBook) -> String {
+    let bands = book
+        .depth_bands
+        .iter()
+        .map(|band| format!("\n{}–{}%  {}", band.from, band.to, format_imbalance(band)))
+        .collect::<String>();
+
+    format!("*Imbalance* {}{}", format_imbalance(&book.total_band()), bands)
+}
+
+fn format_gaps(side: &str, gaps: &[LiquidityGap], precision: Option<Precision>) -> Option<String> {
+    if gaps.is_empty() {
+        return None;
+    }
+
+    let ranges = gaps
+        .iter()
+        .map(|gap| {
+            let nearest = round_price(gap.range.nearest, precision);
+            let farthest = round_price(gap.range.farthest, precision);
+            format!("{}–{}", nearest, farthest)
+        })
+        .collect::<Vec<_>>();
+
+    Some(format!("{} {}", side, ranges.join(", ")))
+}
+
+/// Thin liquidity zones price tends to move fast through, empty if there are none
+fn format_liquidity_gaps(book: &ExtendedOrderBook) -> String {
+    let gaps = [
+        format_gaps("Asks", &book.asks_gaps, book.precision),
+        format_gaps("Bids", &book.bids_gaps, book.precision),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+
+    if gaps.is_empty() {
+        return String::new();
+    }
+
+    format!("\n\n*Gaps*\n{}", gaps.join("\n"))
+}
+
+fn format_coverage(book: &ExtendedOrderBook) -> String {
+    coverage_warnings(book)
+        .into_iter()
+        .map(|warning| format!("\n\n{}", warning))
+        .collect()
+}
+
 pub fn format_message(book: ExtendedOrderBook) -> String {
     let mut f = Formatter::default();
     let asks_vol = format_num(&mut f, book.asks_volume());
     let bids_vol = format_num(&mut f, book.bids_volume());
-    
-    let asks = format_order_book(&mut f, book.asks);
-    let bids = format_order_book(&mut f, book.bids);
-    let last_price = book.last_price.trunc_with_scale(5).normalize();
+    let total = book.total_band();
+    let asks_total = format_num(&mut f, total.asks);
+    let bids_total = format_num(&mut f, total.bids);
+    let coverage = format_coverage(&book);
+    let cumulative_depth = format_cumulative_depth(&mut f, &book);
+    let depth_bands = format_depth_bands(&book);
+    let gaps = format_liquidity_gaps(&book);
+
+    let last_price = book.rounded_last_price();
+    let title = match (book.bucket_size, book.grouping) {
+        (Some(_), grouping) => format!("Top 10 zones of {}% depth, {} wide", book.depth, grouping),
+        (None, Grouping::Levels) => format!("Top 10 limits of {}% depth", book.depth),
+        // no tick size is known or the bucket rounds down to zero
+        (None, grouping) => format!(
+            "Top 10 limits of {}% depth\nGrouping by {} is unavailable for this coin, showing single levels",
+            book.depth, grouping
+        ),
+    };
+    let title = match book.sort {
+        WallSort::Notional => title,
+        WallSort::Score => format!("{}, ranked by score", title),
+    };
+    let title = if book.asks.iter().chain(book.bids.iter()).any(|wall| wall.suspected_spoof) {
+        format!("{}\n{}: walls around this price were pulled in the last hour", title, SPOOF_MARKER)
+    } else {
+        title
+    };
+    let base_asset = book.symbol.strip_suffix("USDT").unwrap_or(&book.symbol);
+    let asks = format_order_book(&mut f, book.asks, book.bucket_size, base_asset);
+    let bids = format_order_book(&mut f, book.bids, book.bucket_size, base_asset);
+
+    let msg = format!(
+        "*{}* • {}\n\n{}\n\n*ASKS*\n{}\n\n*Last price* {}\n\n*BIDS*\n{}\n\nTop 10 asks volume ${}\nTop 10 bids volume ${}\nAll asks within {}% ${}\nAll bids within {}% ${}{}\n\n{}\n\n*Depth*{}{}",
+        book.symbol, book.source, title, asks, last_price, bids, asks_vol, bids_vol,
+        book.depth, asks_total, book.depth, bids_total, gaps, depth_bands, cumulative_depth, coverage
+    );
+
+    escape_markdown_v2(msg)
+}
+
+fn format_market_impact(
+    f: &mut Formatter,
+    side: &str,
+    impact: Option<MarketImpact>,
+    estimate: &ImpactEstimate,
+) -> String {
+    let Some(impact) = impact else {
+        return format!("*{}*\nNo orders received", side);
+    };
+
+    let mut msg = format!(
+        "*{}*\nAverage price {}\nWorst price {}\nSlippage {}%",
+        side,
+        round_price(impact.average_price, estimate.precision),
+        impact.worst_price.normalize(),
+        impact.slippage.round_dp(2).normalize(),
+    );
+
+    if impact.exhausted {
+        msg.push_str(&format!("\n⚠️ Fetched book ends after ${}", format_num(f, impact.filled)));
+    }
+
+    msg
+}
+
+pub fn format_impact(estimate: ImpactEstimate) -> String {
+    let mut f = Formatter::default();
+    let buy = format_market_impact(&mut f, "BUY", estimate.buy, &estimate);
+    let sell = format_market_impact(&mut f, "SELL", estimate.sell, &estimate);
+
+    let msg = format!(
+        "*{}* • {}\n\nMarket order of ${}, last price {}\n\n{}\n\n{}",
+        estimate.symbol,
+        estimate.exchange,
+        format_num(&mut f, estimate.size),
+        round_price(estimate.last_price, estimate.precision),
+        buy,
+        sell
+    );
+
+    escape_markdown_v2(msg)
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+fn format_tracked_wall(f: &mut Formatter, wall: &TrackedWall) -> String {
+    let change = wall.size_change().round_dp(0).normalize();
+    let sign = if change > Decimal::ZERO { "+" } else { "" };
+
+    format!("{}  •  ${} ({}{}%)", wall.price, format_num(f, wall.notional), sign, change)
+}
+
+fn format_tracked_side(f: &mut Formatter, all: &SymbolWalls, side: OrderType, now: u64) -> String {
+    let mut walls = all
+        .walls
+        .iter()
+        .filter(|wall| wall.side == side)
+        .collect::<Vec<_>>();
+
+    // the same order as in the order book, asks on top
+    walls.sort_by_key(|wall| std::cmp::Reverse(wall.price));
+
+    walls
+        .iter()
+        .map(|wall| {
+            let age = format_duration(now.saturating_sub(wall.first_seen));
+            let row = format!("{}  •  for {}", format_tracked_wall(f, wall), age);
+
+            if all.is_suspected(side, wall.price, wall.price) { format!("{}  •  {}", row, SPOOF_MARKER) } else { row }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Current walls of a watched symbol with how long they have been standing,
+/// and the ones gone within the last hour
+pub fn format_tracked_walls(symbol: &str, walls: SymbolWalls, now: u64) -> String {
+    let mut f = Formatter::default();
+    let asks = format_tracked_side(&mut f, &walls, OrderType::Ask, now);
+    let bids = format_tracked_side(&mut f, &walls, OrderType::Bid, now);
+
+    let gone = walls
+        .gone
+        .iter()
+        .map(|gone| {
+            let side = match gone.wall.side {
+                OrderType::Ask => "Ask",
+                OrderType::Bid => "Bid",
+            };
+            let lived = format_duration(gone.gone_at.saturating_sub(gone.wall.first_seen));
+            let ago = format_duration(now.saturating_sub(gone.gone_at));
+            let reason = match gone.reason {
+                GoneReason::Outranked => "outranked",
+                GoneReason::Filled => "filled",
+                GoneReason::Pulled => "pulled",
+                GoneReason::PulledOnApproach => "pulled on approach",
+            };
+            format!(
+                "\n{} {}  •  stood {}, {} {} ago",
+                side,
+                format_tracked_wall(&mut f, &gone.wall),
+                lived,
+                reason,
+                ago
+            )
+        })
+        .collect::<String>();
+
+    let gone = if gone.is_empty() { gone } else { format!("\n\n*GONE*{}", gone) };
+
+    let msg = format!(
+        "*{}* • {}\n\nTracked walls, updated {} ago\n\n*ASKS*\n{}\n\n*BIDS*\n{}{}",
+        symbol,
+        walls.exchange,
+        format_duration(now.saturating_sub(walls.updated_at)),
+        asks,
+        bids,
+        gone
+    );
+
+    escape_markdown_v2(msg)
+}
+
+fn format_iceberg_side(f: &mut Formatter, icebergs: &Icebergs, side: OrderType, base_asset: &str) -> String {
+    let mut levels = icebergs
+        .levels
+        .iter()
+        .filter(|level| level.side == side)
+        .collect::<Vec<_>>();
+
+    if levels.is_empty() {
+        return "None".to_string();
+    }
+
+    // the same order as in the order book, asks on top
+    levels.sort_by_key(|level| std::cmp::Reverse(level.price));
+
+    levels
+        .into_iter()
+        .map(|level| {
+            format!(
+                "{}  •  traded ${} ({} {})  •  visible at most {} {}  •  ×{}  •  {} trades",
+                level.price,
+                format_num(f, level.traded * level.price),
+                format_num(f, level.traded),
+                base_asset,
+                format_num(f, level.max_visible),
+                base_asset,
+                level.hidden_ratio().unwrap_or_default().round_dp(1).normalize(),
+                level.trades
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn format_icebergs(symbol: &str, icebergs: Icebergs) -> String {
+    let mut f = Formatter::default();
+    let base_asset = symbol.strip_suffix("USDT").unwrap_or(symbol);
+    let asks = format_iceberg_side(&mut f, &icebergs, OrderType::Ask, base_asset);
+    let bids = format_iceberg_side(&mut f, &icebergs, OrderType::Bid, base_asset);
 
     let msg = format!(
-        "*{}*\n\nTop 10 limits of {}% depth\n\n*ASKS*\n{}\n\n*Last price* {}\n\n*BIDS*\n{}\n\nAsks volume ${}\nBids volume ${}",
-        book.symbol, book.depth, asks, last_price, bids, asks_vol, bids_vol
+        "*{}* • Binance\n\nLevels that traded far more than they ever showed over the last {}\n\n*ASKS*\n{}\n\n*Last price* {}\n\n*BIDS*\n{}",
+        symbol,
+        format_duration(icebergs.window_secs),
+        asks,
+        icebergs.last_price.normalize(),
+        bids
     );
 
     escape_markdown_v2(msg)
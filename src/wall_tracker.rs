@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+
+use log::info;
+use rust_decimal::Decimal;
+use tokio::sync::RwLock;
+
+use crate::exchange::{ExchangeId, OrderBookSource, Precision};
+use crate::local_book::AggTrade;
+use crate::order_book::{ExtendedOrderBook, OrderBook, OrderType, Wall, round_price};
+
+/// Walls that left the top walls are kept this long to be shown in `/walls`
+const GONE_WALLS_TTL_SECS: u64 = 3600;
+/// A wall pulled with the price closer than this, in percent, was pulled on approach
+const APPROACH_PERCENT: Decimal = Decimal::ONE;
+/// Pulls at prices this close, in percent, count as the same wall reposted
+const SPOOF_PRICE_TOLERANCE: Decimal = Decimal::from_parts(1, 0, 0, false, 1);
+/// Pulls at the same price within the TTL after which a wall there is suspected
+const SPOOF_PULLS: usize = 2;
+
+/// Wall seen in consecutive snapshots at the same price.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackedWall {
+    pub side: OrderType,
+    pub price: Decimal,
+    pub first_notional: Decimal,
+    pub notional: Decimal,
+    pub base_qty: Decimal,
+    pub first_seen: u64,
+}
+
+impl TrackedWall {
+    /// Change of the wall size since it was first seen, in percent
+    pub fn size_change(&self) -> Decimal {
+        if self.first_notional.is_zero() {
+            return Decimal::ZERO;
+        }
+
+        (self.notional - self.first_notional) / self.first_notional * Decimal::ONE_HUNDRED
+    }
+
+    /// A wall that lost less than half of its size is still standing, it was pushed
+    /// out of the top walls or the depth. Otherwise the missing part was filled if at
+    /// least half of it traded at or through the price, or, without trades, if the
+    /// last price reached the wall.
+    fn classify_gone(&self, snapshot: &Snapshot) -> GoneReason {
+        let Snapshot { book, last_price, trades, precision } = *snapshot;
+        let side = match self.side {
+            OrderType::Ask => &book.asks,
+            OrderType::Bid => &book.bids,
+        };
+        // tracked prices are rounded the same way, several raw levels may fall into one
+        let remaining = side
+            .iter()
+            .filter(|entity| round_price(entity.price, precision) == self.price)
+            .map(|entity| entity.qty)
+            .sum::<Decimal>();
+
+        if remaining * Decimal::TWO >= self.base_qty {
+            return GoneReason::Outranked;
+        }
+
+        let reached = |price: Decimal| match self.side {
+            OrderType::Ask => price >= self.price,
+            OrderType::Bid => price <= self.price,
+        };
+
+        let filled = match trades {
+            Some(trades) => {
+                let traded = trades
+                    .iter()
+                    .filter(|trade| reached(trade.price))
+                    .map(|trade| trade.qty)
+                    .sum::<Decimal>();
+
+                traded * Decimal::TWO >= self.base_qty - remaining
+            }
+            None => reached(last_price),
+        };
+
+        let approached = !last_price.is_zero()
+            && (self.price - last_price).abs() / last_price * Decimal::ONE_HUNDRED <= APPROACH_PERCENT;
+
+        if filled {
+            GoneReason::Filled
+        } else if approached {
+            GoneReason::PulledOnApproach
+        } else {
+            GoneReason::Pulled
+        }
+    }
+}
+
+/// Why a wall left the top walls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoneReason {
+    /// Still in the book, but smaller than the current top walls or beyond the depth
+    Outranked,
+    Filled,
+    Pulled,
+    /// Pulled with the price within `APPROACH_PERCENT` of the wall
+    PulledOnApproach,
+}
+
+/// Wall that was pulled, filled or pushed out of the top walls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoneWall {
+    pub wall: TrackedWall,
+    pub gone_at: u64,
+    pub reason: GoneReason,
+}
+
+/// Walls of a symbol as of the last snapshot.
+#[derive(Debug, Clone)]
+pub struct SymbolWalls {
+    pub exchange: ExchangeId,
+    pub walls: Vec<TrackedWall>,
+    pub gone: Vec<GoneWall>,
+    pub updated_at: u64,
+}
+
+impl SymbolWalls {
+    fn new(exchange: ExchangeId) -> Self {
+        SymbolWalls {
+            exchange,
+            walls: Vec::new(),
+            gone: Vec::new(),
+            updated_at: 0,
+        }
+    }
+
+    /// Matches the current top walls against the tracked ones by side and price.
+    /// Outranked walls that make it back to the top keep their history.
+    /// Walls that are gone are checked against the book they were taken from and
+    /// the trades since the previous snapshot, if those are known.
+    fn update(&mut self, current: Vec<(OrderType, &Wall)>, snapshot: &Snapshot, now: u64) {
+        let mut walls = Vec::with_capacity(current.len());
+
+        for (side, wall) in current {
+            let is_same = |tracked: &TrackedWall| tracked.side == side && tracked.price == wall.level.price;
+            let tracked = self
+                .walls
+                .iter()
+                .position(is_same)
+                .map(|index| self.walls.swap_remove(index))
+                .or_else(|| {
+                    self.gone
+                        .iter()
+                        .position(|gone| gone.reason == GoneReason::Outranked && is_same(&gone.wall))
+                        .map(|index| self.gone.swap_remove(index).wall)
+                });
+
+            let (notional, base_qty) = (wall.level.notional, wall.level.base_qty);
+            walls.push(match tracked {
+                Some(tracked) => TrackedWall { notional, base_qty, ..tracked },
+                None => TrackedWall {
+                    side,
+                    price: wall.level.price,
+                    first_notional: notional,
+                    notional,
+                    base_qty,
+                    first_seen: now,
+                },
+            });
+        }
+
+        // whatever was not matched is gone
+        let gone = self.walls.drain(..).map(|wall| GoneWall {
+            reason: wall.classify_gone(snapshot),
+            wall,
+            gone_at: now,
+        });
+        self.gone.extend(gone);
+        self.gone.retain(|gone| now.saturating_sub(gone.gone_at) < GONE_WALLS_TTL_SECS);
+
+        self.walls = walls;
+        self.updated_at = now;
+    }
+
+    /// A wall between the prices is suspected to be a spoof if walls around there
+    /// were pulled while the price approached them or pulled repeatedly.
+    pub fn is_suspected(&self, side: OrderType, low: Decimal, high: Decimal) -> bool {
+        let tolerance = SPOOF_PRICE_TOLERANCE / Decimal::ONE_HUNDRED;
+        let (low, high) = (low - low * tolerance, high + high * tolerance);
+
+        let pulls = self
+            .gone
+            .iter()
+            .filter(|gone| gone.wall.side == side && gone.wall.price >= low && gone.wall.price <= high)
+            .map(|gone| gone.reason)
+            .filter(|reason| matches!(reason, GoneReason::Pulled | GoneReason::PulledOnApproach))
+            .collect::<Vec<_>>();
+
+        pulls.contains(&GoneReason::PulledOnApproach) || pulls.len() >= SPOOF_PULLS
+    }
+}
+
+/// Full order book a snapshot of walls was taken from.
+#[derive(Clone, Copy)]
+pub struct Snapshot<'a> {
+    pub book: &'a OrderBook,
+    pub last_price: Decimal,
+    /// Trades since the previous snapshot, `None` if they are unknown
+    pub trades: Option<&'a [AggTrade]>,
+    /// Precision the wall prices were rounded with
+    pub precision: Option<Precision>,
+}
+
+/// Top walls of watched symbols followed across periodic snapshots.
+#[derive(Debug, Default)]
+pub struct WallTracker {
+    symbols: RwLock<HashMap<String, SymbolWalls>>,
+}
+
+impl WallTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn update(&self, exchange: ExchangeId, book: &ExtendedOrderBook, snapshot: &Snapshot<'_>, now: u64) {
+        let current = book
+            .asks
+            .iter()
+            .map(|wall| (OrderType::Ask, wall))
+            .chain(book.bids.iter().map(|wall| (OrderType::Bid, wall)))
+            .collect();
+
+        let mut symbols = self.symbols.write().await;
+        let walls = symbols
+            .entry(book.symbol.clone())
+            .or_insert_with(|| SymbolWalls::new(exchange));
+
+        walls.update(current, snapshot, now);
+        info!("Tracking {} walls of {}, {} gone recently", walls.walls.len(), book.symbol, walls.gone.len());
+    }
+
+    pub async fn get(&self, symbol: &str) -> Option<SymbolWalls> {
+        self.symbols.read().await.get(symbol).cloned()
+    }
+
+    /// Time of the last snapshot of the symbol
+    pub async fn updated_at(&self, symbol: &str) -> Option<u64> {
+        self.symbols.read().await.get(symbol).map(|walls| walls.updated_at)
+    }
+
+    /// Flags walls of the tracked exchange near which walls were pulled before.
+    pub async fn mark_suspected(&self, book: &mut ExtendedOrderBook) {
+        let symbols = self.symbols.read().await;
+        let Some(walls) = symbols.get(&book.symbol) else {
+            return;
+        };
+
+        let bucket_size = book.bucket_size.unwrap_or_default();
+
+        for (side, side_walls) in [(OrderType::Ask, &mut book.asks), (OrderType::Bid, &mut book.bids)] {
+            for wall in side_walls.iter_mut() {
+                let tracked = match book.source {
+                    OrderBookSource::Exchange(exchange) => exchange == walls.exchange,
+                    OrderBookSource::Aggregated => wall.level.exchange == Some(walls.exchange),
+                };
+                let price = wall.level.price;
+
+                wall.suspected_spoof = tracked && walls.is_suspected(side, price, price + bucket_size);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::order_book::{Level, OrderBookEntity, WallScore};
+
+    use super::*;
+
+    fn wall(price: i64, notional: i64) -> Wall {
+        Wall {
+            level: Level {
+                price: Decimal::from(price),
+                base_qty: Decimal::from(notional) / Decimal::from(price),
+                notional: Decimal::from(notional),
+                exchange: None,
+            },
+            score: WallScore {
+                size_vs_median: Decimal::ONE,
+                side_share: Decimal::ONE,
+                distance: Decimal::ONE,
+                score: Decimal::ONE,
+            },
+            notional_before: Decimal::ZERO,
+            suspected_spoof: false,
+        }
+    }
+
+    fn snapshot<'a>(book: &'a OrderBook, last_price: i64, trades: Option<&'a [AggTrade]>) -> Snapshot<'a> {
+        Snapshot {
+            book,
+            last_price: Decimal::from(last_price),
+            trades,
+            precision: None,
+        }
+    }
+
+    #[test]
+    fn test_update_walls() {
+        let mut walls = SymbolWalls::new(ExchangeId::Binance);
+        let book = OrderBook { asks: vec![], bids: vec![] };
+        let (ask, bid) = (wall(110, 1000), wall(90, 500));
+
+        walls.update(vec![(OrderType::Ask, &ask), (OrderType::Bid, &bid)], &snapshot(&book, 100, None), 100);
+        assert_eq!(walls.walls.len(), 2);
+
+        // the ask grew, the bid is gone and a new bid showed up at the same price as the ask
+        let (grown, new) = (wall(110, 1500), wall(110, 700));
+        walls.update(vec![(OrderType::Ask, &grown), (OrderType::Bid, &new)], &snapshot(&book, 100, None), 160);
+
+        let grown = &walls.walls[0];
+        assert_eq!(grown.first_seen, 100);
+        assert_eq!(grown.notional, Decimal::from(1500));
+        assert_eq!(grown.size_change(), Decimal::from(50));
+
+        let new = &walls.walls[1];
+        assert_eq!((new.side, new.first_seen), (OrderType::Bid, 160));
+
+        assert_eq!(walls.gone.len(), 1);
+        assert_eq!(walls.gone[0].wall.price, Decimal::from(90));
+        assert_eq!(walls.gone[0].gone_at, 160);
+
+        walls.update(vec![], &snapshot(&book, 100, None), 160 + GONE_WALLS_TTL_SECS);
+        assert_eq!(walls.gone.len(), 2);
+        assert!(walls.gone.iter().all(|gone| gone.gone_at == 160 + GONE_WALLS_TTL_SECS));
+    }
+
+    #[test]
+    fn test_outranked_wall_returns() {
+        let mut walls = SymbolWalls::new(ExchangeId::Binance);
+        let ask = wall(110, 1000);
+        walls.update(vec![(OrderType::Ask, &ask)], &snapshot(&OrderBook { asks: vec![], bids: vec![] }, 100, None), 100);
+
+        // the ask is still in the book, but no longer among the top walls
+        let book = OrderBook {
+            asks: vec![OrderBookEntity::new(Decimal::from(110), Decimal::from(10))],
+            bids: vec![],
+        };
+        walls.update(vec![], &snapshot(&book, 100, None), 160);
+        assert_eq!(walls.gone[0].reason, GoneReason::Outranked);
+
+        let back = wall(110, 1200);
+        walls.update(vec![(OrderType::Ask, &back)], &snapshot(&book, 100, None), 220);
+        assert!(walls.gone.is_empty());
+        assert_eq!(walls.walls[0].first_seen, 100);
+        assert_eq!(walls.walls[0].size_change(), Decimal::from(20));
+    }
+
+    #[test]
+    fn test_classify_gone_walls() {
+        // 10 base each
+        let (outranked, filled, pulled, approached) = (wall(110, 1100), wall(105, 1050), wall(90, 900), wall(99, 990));
+
+        let mut walls = SymbolWalls::new(ExchangeId::Binance);
+        let current = vec![
+            (OrderType::Ask, &outranked),
+            (OrderType::Ask, &filled),
+            (OrderType::Bid, &pulled),
+            (OrderType::Bid, &approached),
+        ];
+        walls.update(current, &snapshot(&OrderBook { asks: vec![], bids: vec![] }, 100, None), 100);
+
+        // the outranked wall is still there, 6 traded at or above 105 out of the 10 removed
+        let book = OrderBook {
+            asks: vec![OrderBookEntity::new(Decimal::from(110), Decimal::from(8))],
+            bids: vec![],
+        };
+        let trades = [(100, 20), (105, 4), (106, 2)].map(|(price, qty)| AggTrade {
+            price: Decimal::from(price),
+            qty: Decimal::from(qty),
+            time: 0,
+            is_buyer_maker: false,
+        });
+        walls.update(vec![], &snapshot(&book, 100, Some(&trades)), 160);
+
+        let reasons = walls.gone.iter().map(|gone| (gone.wall.price, gone.reason)).collect::<HashMap<_, _>>();
+        assert_eq!(reasons[&Decimal::from(110)], GoneReason::Outranked);
+        assert_eq!(reasons[&Decimal::from(105)], GoneReason::Filled);
+        assert_eq!(reasons[&Decimal::from(90)], GoneReason::Pulled);
+        assert_eq!(reasons[&Decimal::from(99)], GoneReason::PulledOnApproach);
+
+        // without trades a wall counts as filled once the price reached it
+        let mut walls = SymbolWalls::new(ExchangeId::Binance);
+        walls.update(vec![(OrderType::Bid, &pulled)], &snapshot(&book, 100, None), 100);
+        walls.update(vec![], &snapshot(&book, 89, None), 160);
+        assert_eq!(walls.gone[0].reason, GoneReason::Filled);
+    }
+
+    #[test]
+    fn test_gone_wall_matches_rounded_price() {
+        // without precision the tracked price is truncated to 5 decimals
+        let raw_price = Decimal::new(123456, 6);
+        let book = OrderBook {
+            asks: vec![OrderBookEntity::new(raw_price, Decimal::from(1000))],
+            bids: vec![],
+        };
+        let tracked = TrackedWall {
+            side: OrderType::Ask,
+            price: round_price(raw_price, None),
+            first_notional: Decimal::from(123),
+            notional: Decimal::from(123),
+            base_qty: Decimal::from(1000),
+            first_seen: 100,
+        };
+
+        let snapshot = Snapshot {
+            last_price: Decimal::new(12, 2),
+            ..snapshot(&book, 0, None)
+        };
+        assert_eq!(tracked.classify_gone(&snapshot), GoneReason::Outranked);
+    }
+
+    #[test]
+    fn test_suspected_walls() {
+        let mut walls = SymbolWalls::new(ExchangeId::Binance);
+        let book = OrderBook { asks: vec![], bids: vec![] };
+        let (far, near) = (wall(90, 900), wall(99, 990));
+
+        walls.update(vec![(OrderType::Bid, &far), (OrderType::Bid, &near)], &snapshot(&book, 100, None), 100);
+        walls.update(vec![], &snapshot(&book, 100, None), 160);
+
+        // pulled on approach once is enough, far from the price it has to happen twice
+        assert!(walls.is_suspected(OrderType::Bid, Decimal::new(9905, 2), Decimal::new(9905, 2)));
+        assert!(!walls.is_suspected(OrderType::Ask, Decimal::from(99), Decimal::from(99)));
+        assert!(!walls.is_suspected(OrderType::Bid, Decimal::from(90), Decimal::from(90)));
+
+        walls.update(vec![(OrderType::Bid, &far)], &snapshot(&book, 100, None), 220);
+        walls.update(vec![], &snapshot(&book, 100, None), 280);
+        assert!(walls.is_suspected(OrderType::Bid, Decimal::from(90), Decimal::from(90)));
+    }
+}